@@ -5,7 +5,7 @@ use std::time::Instant;
 
 fn wait_for_file(file: impl AsFd) {
     // Loop to avoid spurious wakeups
-    while !host_api::wait_until_ready_for_read(&[file.as_fd()])
+    while !host_api::wait_until_ready_for_read(&[file.as_fd()], None)
         .iter()
         .any(|fd| fd.as_fd().as_raw_fd() == file.as_fd().as_raw_fd())
     {}
@@ -1,13 +1,38 @@
 use bytemuck::Zeroable;
 use host_api_sys as ffi;
-use std::os::fd::{AsRawFd, BorrowedFd};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::time::Duration;
 
-pub fn wait_until_ready_for_read<'a>(fds: &[BorrowedFd<'a>]) -> Vec<BorrowedFd<'a>> {
+/// Waits until any of the given fds can be read from, or until the timeout expires, in which case
+/// no fds are returned.
+pub fn wait_until_ready_for_read<'a>(
+    fds: &[BorrowedFd<'a>],
+    timeout: Option<Duration>,
+) -> Vec<BorrowedFd<'a>> {
+    wait_until_ready(fds, ffi::InterestFlags::READ, timeout)
+}
+
+/// Waits until any of the given fds can be written to, or until the timeout expires, in which case
+/// no fds are returned.
+pub fn wait_until_ready_for_write<'a>(
+    fds: &[BorrowedFd<'a>],
+    timeout: Option<Duration>,
+) -> Vec<BorrowedFd<'a>> {
+    wait_until_ready(fds, ffi::InterestFlags::WRITE, timeout)
+}
+
+fn wait_until_ready<'a>(
+    fds: &[BorrowedFd<'a>],
+    flags: ffi::InterestFlags,
+    timeout: Option<Duration>,
+) -> Vec<BorrowedFd<'a>> {
     let interests: Vec<ffi::Interest> = fds
         .iter()
         .map(|fd| ffi::Interest {
             fd: fd.as_raw_fd(),
-            interest_flags: ffi::InterestFlags::READ.bits(),
+            interest_flags: flags.bits(),
         })
         .collect();
 
@@ -20,6 +45,9 @@ pub fn wait_until_ready_for_read<'a>(fds: &[BorrowedFd<'a>]) -> Vec<BorrowedFd<'
             interests.as_ptr() as i64,
             ready.as_mut_ptr() as i64,
             interests.len() as i64,
+            timeout.map_or(-1, |timeout| {
+                timeout.as_nanos().min(i64::MAX as u128) as i64
+            }),
         )
     };
 
@@ -31,3 +59,181 @@ pub fn wait_until_ready_for_read<'a>(fds: &[BorrowedFd<'a>]) -> Vec<BorrowedFd<'
         .map(|ready| unsafe { BorrowedFd::borrow_raw(ready.fd) })
         .collect()
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceType {
+    Ethernet,
+    Wireless,
+}
+
+impl DeviceType {
+    /// Name of the device with the given index under `/dev/`.
+    pub fn device_name(&self, idx: u32) -> String {
+        match self {
+            DeviceType::Ethernet => format!("ethernet{idx}"),
+            DeviceType::Wireless => format!("wireless{idx}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceEvent {
+    Plugged(DeviceType, u32),
+    Unplugged(DeviceType, u32),
+}
+
+/// Notifications of devices being plugged in or unplugged, read from `/dev/events`. Only devices
+/// plugged in or unplugged after opening are reported.
+pub struct DeviceEvents {
+    file: File,
+}
+
+impl DeviceEvents {
+    pub fn open() -> io::Result<DeviceEvents> {
+        Ok(DeviceEvents {
+            file: File::open("/dev/events")?,
+        })
+    }
+
+    /// Reads the next event, or `None` if there are none waiting. Use [`wait_until_ready_for_read`]
+    /// to wait for one to arrive.
+    pub fn read(&mut self) -> io::Result<Option<DeviceEvent>> {
+        let mut event = ffi::DeviceEvent::zeroed();
+        let n = self.file.read(bytemuck::bytes_of_mut(&mut event))?;
+
+        if n == 0 {
+            return Ok(None);
+        }
+
+        let device_type = match event.device_type {
+            ffi::DEVICE_TYPE_ETHERNET => DeviceType::Ethernet,
+            ffi::DEVICE_TYPE_WIRELESS => DeviceType::Wireless,
+            other => return Err(invalid_data(format!("unknown device type {other}"))),
+        };
+
+        match event.kind {
+            ffi::DEVICE_EVENT_PLUGGED => {
+                Ok(Some(DeviceEvent::Plugged(device_type, event.device_idx)))
+            }
+            ffi::DEVICE_EVENT_UNPLUGGED => {
+                Ok(Some(DeviceEvent::Unplugged(device_type, event.device_idx)))
+            }
+            other => Err(invalid_data(format!("unknown device event {other}"))),
+        }
+    }
+}
+
+impl AsFd for DeviceEvents {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+/// A keyboard or mouse, read from `/dev/input/`. Events are shared between every open handle to
+/// the same device.
+pub struct InputDevice {
+    file: File,
+}
+
+impl InputDevice {
+    pub fn open_keyboard() -> io::Result<InputDevice> {
+        Ok(InputDevice {
+            file: File::open("/dev/input/keyboard")?,
+        })
+    }
+
+    pub fn open_mouse() -> io::Result<InputDevice> {
+        Ok(InputDevice {
+            file: File::open("/dev/input/mouse")?,
+        })
+    }
+
+    /// Reads the next event, or `None` if there are none waiting. Use [`wait_until_ready_for_read`]
+    /// to wait for one to arrive.
+    pub fn read(&mut self) -> io::Result<Option<ffi::InputEvent>> {
+        let mut event = ffi::InputEvent::zeroed();
+        let n = self.file.read(bytemuck::bytes_of_mut(&mut event))?;
+        Ok((n != 0).then_some(event))
+    }
+}
+
+impl AsFd for InputDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+/// A computer's display, drawn on by writing pixels to `/dev/fb0`.
+pub struct Framebuffer {
+    file: File,
+    info: ffi::FramebufferInfo,
+}
+
+impl Framebuffer {
+    pub fn open() -> io::Result<Framebuffer> {
+        let mut info = ffi::FramebufferInfo::zeroed();
+        // SAFETY: info is a valid FramebufferInfo for the host to fill in
+        unsafe { ffi::framebuffer_info(&mut info as *mut _ as i64) };
+
+        if info.pixel_format != ffi::PIXEL_FORMAT_RGBA8888 {
+            return Err(invalid_data(format!(
+                "unknown pixel format {}",
+                info.pixel_format
+            )));
+        }
+
+        Ok(Framebuffer {
+            file: OpenOptions::new().read(true).write(true).open("/dev/fb0")?,
+            info,
+        })
+    }
+
+    /// Resolution and layout of the display.
+    pub fn info(&self) -> ffi::FramebufferInfo {
+        self.info
+    }
+
+    /// Draws RGBA pixels at the given position, `width` pixels per row, with as many rows as the
+    /// pixels fill.
+    pub fn draw(&mut self, x: u32, y: u32, width: u32, pixels: &[u8]) -> io::Result<()> {
+        let row_len = width as usize * 4;
+        if row_len == 0 || !pixels.chunks_exact(row_len).remainder().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pixels do not fill whole rows",
+            ));
+        }
+
+        let height = (pixels.len() / row_len) as u32;
+        let right = x
+            .checked_add(width)
+            .filter(|&right| right <= self.info.width);
+        let bottom = y
+            .checked_add(height)
+            .filter(|&bottom| bottom <= self.info.height);
+        if right.is_none() || bottom.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pixels do not fit on the display",
+            ));
+        }
+
+        for (row, pixels) in pixels.chunks(row_len).enumerate() {
+            let offset = (y + row as u32) * self.info.stride + x * 4;
+            self.file.seek(SeekFrom::Start(offset as u64))?;
+            self.file.write_all(pixels)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl AsFd for Framebuffer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
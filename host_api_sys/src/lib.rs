@@ -9,7 +9,21 @@ extern "C" {
     /// # Notes
     /// If the given [`Interest`] refers to a regular file, this function will immediately return,
     /// as regular files do not properly support non blocking mode.
-    pub fn wait_until_ready(interests_ptr: i64, ready_ptr: i64, len: i64) -> i64;
+    ///
+    /// Each returned [`Ready`] carries only the subset of its [`Interest`]'s flags which are ready.
+    ///
+    /// If `timeout_ns` is non-negative and no interest becomes ready within that many nanoseconds,
+    /// zero is returned. A negative `timeout_ns` waits indefinitely.
+    pub fn wait_until_ready(interests_ptr: i64, ready_ptr: i64, len: i64, timeout_ns: i64) -> i64;
+}
+
+#[cfg(target_os = "wasi")]
+#[link(wasm_import_module = "display")]
+extern "C" {
+    /// # Safety contract
+    /// `info_ptr` must point to a [`FramebufferInfo`], which is filled in with the resolution and
+    /// pixel format of `/dev/fb0`. Returns zero.
+    pub fn framebuffer_info(info_ptr: i64) -> i64;
 }
 
 use bytemuck::{Pod, Zeroable};
@@ -19,6 +33,7 @@ bitflags::bitflags! {
     #[repr(transparent)]
     pub struct InterestFlags: u32 {
         const READ = 0b01;
+        const WRITE = 0b10;
     }
 }
 
@@ -47,3 +62,78 @@ impl Ready {
         InterestFlags::from_bits_retain(self.interest_flags)
     }
 }
+
+pub const DEVICE_EVENT_PLUGGED: u32 = 0;
+pub const DEVICE_EVENT_UNPLUGGED: u32 = 1;
+
+pub const DEVICE_TYPE_ETHERNET: u32 = 0;
+pub const DEVICE_TYPE_WIRELESS: u32 = 1;
+
+/// Record read from `/dev/events` whenever a device is plugged in or unplugged. `kind` is one of
+/// the `DEVICE_EVENT_*` constants, and `device_type` is one of the `DEVICE_TYPE_*` constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Pod, Zeroable)]
+#[repr(C)]
+pub struct DeviceEvent {
+    pub kind: u32,
+    pub device_type: u32,
+    pub device_idx: u32,
+}
+
+pub type MacAddress = [u8; 6];
+
+/// Destination address which frames are flooded to every port of a switch.
+pub const BROADCAST_MAC: MacAddress = [0xff; 6];
+
+/// Header which frames sent through a simulated switch must start with, so that the switch can
+/// learn which port each address is behind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Pod, Zeroable)]
+#[repr(C)]
+pub struct FrameHeader {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+}
+
+pub const INPUT_EVENT_SYN: u16 = 0;
+pub const INPUT_EVENT_KEY: u16 = 1;
+pub const INPUT_EVENT_REL: u16 = 2;
+
+/// Code of the sync event which follows each group of events describing one change.
+pub const INPUT_SYN_REPORT: u16 = 0;
+
+pub const INPUT_REL_X: u16 = 0x00;
+pub const INPUT_REL_Y: u16 = 0x01;
+pub const INPUT_REL_WHEEL: u16 = 0x08;
+
+pub const INPUT_BTN_LEFT: u16 = 0x110;
+pub const INPUT_BTN_RIGHT: u16 = 0x111;
+pub const INPUT_BTN_MIDDLE: u16 = 0x112;
+
+pub const INPUT_KEY_RELEASED: i32 = 0;
+pub const INPUT_KEY_PRESSED: i32 = 1;
+
+/// Record read from `/dev/input/keyboard` or `/dev/input/mouse`, laid out like a Linux evdev event.
+/// `kind` is one of the `INPUT_EVENT_*` constants. Key events carry a Linux key or button code and
+/// one of the `INPUT_KEY_*` constants, and relative events carry one of the `INPUT_REL_*` axes and
+/// how far it moved. `time_us` is when the event happened, in microseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Pod, Zeroable)]
+#[repr(C)]
+pub struct InputEvent {
+    pub time_us: u64,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Each pixel is four bytes: red, green, blue and alpha.
+pub const PIXEL_FORMAT_RGBA8888: u32 = 0;
+
+/// Layout of `/dev/fb0`. Pixels are stored row by row from the top left, with each row starting
+/// `stride` bytes after the previous one. `pixel_format` is one of the `PIXEL_FORMAT_*` constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Pod, Zeroable)]
+#[repr(C)]
+pub struct FramebufferInfo {
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub pixel_format: u32,
+}
@@ -1,7 +1,11 @@
 use anyhow::Result;
+use sandboxer::devices::stdin::Stdin;
 use sandboxer::devices::AttachedDuplexLink;
-use sandboxer::Computer;
+use sandboxer::output::OutputStream;
+use sandboxer::{Computer, ComputerVm};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::task::JoinHandle;
 use wasmtime::Module;
 
 #[tokio::main]
@@ -17,12 +21,69 @@ async fn main() -> Result<()> {
     computer1.add_ethernet(link1);
     computer2.add_ethernet(link2);
 
-    tokio::spawn(async move {
+    forward_terminal_input(vec![computer1.stdin(), computer2.stdin()]);
+
+    let computer2 = tokio::spawn(async move {
         tokio::time::sleep(Duration::from_secs(3)).await;
-        computer2.resume().await.unwrap()
+        run(computer2).await
     });
 
-    computer1.resume().await?;
+    run(computer1).await?;
+    computer2.await??;
 
     Ok(())
 }
+
+/// Runs the computer to completion, printing its output as it is produced.
+async fn run(mut vm: ComputerVm) -> Result<()> {
+    let id = vm.computer().read().unwrap().id();
+    let stdout = print_lines(vm.take_stdout().unwrap(), format!("[{id}]"));
+    let stderr = print_lines(vm.take_stderr().unwrap(), format!("[{id} stderr]"));
+
+    let res = vm.resume().await;
+
+    // Dropping the VM ends its output streams, once everything written has been printed
+    drop(vm);
+    stdout.await?;
+    stderr.await?;
+    println!("[{id}] Finished execution");
+
+    res
+}
+
+fn print_lines(mut stream: OutputStream, prefix: String) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(line) = stream.read_line().await {
+            println!("{prefix} {line}");
+        }
+    })
+}
+
+/// Forwards lines typed into the terminal to the selected computer's stdin, starting with the
+/// first. Typing `:N` selects the Nth computer instead.
+fn forward_terminal_input(stdins: Vec<Arc<Stdin>>) {
+    std::thread::spawn(move || {
+        let mut selected = 0;
+
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+
+            if let Some(n) = line.strip_prefix(':') {
+                match n.trim().parse::<usize>() {
+                    Ok(n) if (1..=stdins.len()).contains(&n) => selected = n - 1,
+                    _ => eprintln!("No computer {n}, there are {}", stdins.len()),
+                }
+                continue;
+            }
+
+            let input = format!("{line}\n");
+            if stdins[selected].push(input.as_bytes()) < input.len() {
+                eprintln!("Computer {} is not keeping up with input", selected + 1);
+            }
+        }
+
+        for stdin in &stdins {
+            stdin.close();
+        }
+    });
+}
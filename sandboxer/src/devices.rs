@@ -1,21 +1,313 @@
+mod capture;
+mod events;
+pub mod framebuffer;
+pub mod input;
+pub(crate) mod numbers;
+mod pseudo;
+pub mod stdin;
+pub mod switch;
+pub mod tty;
 pub mod virtual_fs;
+pub mod wireless;
 
-use event_listener::Event;
-use futures::future::Either;
-use std::collections::VecDeque;
+use crate::devices::capture::Capture;
+use crate::devices::events::EventQueue;
+use crate::devices::framebuffer::Framebuffer;
+use crate::devices::input::InputDevice;
+use crate::devices::numbers::{DeviceNumbers, MINOR_COUNT};
+use crate::devices::pseudo::RandomSource;
+use crate::devices::stdin::Stdin;
+use crate::devices::tty::Tty;
+use crate::devices::wireless::AttachedRadio;
+use crate::Position;
+use event_listener::{Event, EventListener};
+use futures::future::{BoxFuture, Either};
+use futures::FutureExt;
+use host_api_sys::{
+    DeviceEvent, DEVICE_EVENT_PLUGGED, DEVICE_EVENT_UNPLUGGED, DEVICE_TYPE_ETHERNET,
+    DEVICE_TYPE_WIRELESS,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, VecDeque};
 use std::future::Future;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::io::{self, IoSlice, IoSliceMut, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+use wasi_common::file::Filestat;
+use wasi_common::ErrorExt;
+
+/// Number of bytes a link buffers in each direction by default.
+pub const DEFAULT_LINK_CAPACITY: usize = 64 * 1024;
+
+/// Properties of a simulated link, applied to both of its directions.
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    /// Maximum number of bytes buffered in each direction before writes are refused. This includes
+    /// bytes still in transit.
+    pub capacity: usize,
+    /// If set, the link carries discrete frames of at most this many bytes rather than a byte
+    /// stream. Each write is delivered as one frame, and each read returns at most one frame.
+    pub max_frame_size: Option<usize>,
+    /// Time taken for bytes to cross the link once they have been sent.
+    pub latency: Duration,
+    /// Maximum throughput of the link in bytes per second. Unlimited if not set.
+    pub bandwidth: Option<u64>,
+    /// Chance of each write being lost in transit.
+    pub drop_probability: f64,
+    /// Chance of each write having a single bit flipped in transit.
+    pub corrupt_probability: f64,
+    /// Seed for random loss and corruption. Random if not set.
+    pub seed: Option<u64>,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            capacity: DEFAULT_LINK_CAPACITY,
+            max_frame_size: None,
+            latency: Duration::ZERO,
+            bandwidth: None,
+            drop_probability: 0.0,
+            corrupt_probability: 0.0,
+            seed: None,
+        }
+    }
+}
+
+/// The imperfect physical medium one direction of a link travels over.
+struct Wire {
+    latency: Duration,
+    bandwidth: Option<u64>,
+    drop_probability: f64,
+    corrupt_probability: f64,
+    rng: StdRng,
+    /// When everything sent so far will have been put on the wire.
+    busy_until: Instant,
+}
+
+impl Wire {
+    /// Creates the wire described by the config, or `None` if the link is perfect.
+    fn new(config: &LinkConfig, seed: Option<u64>) -> Option<Wire> {
+        let is_perfect = config.latency.is_zero()
+            && config.bandwidth.is_none()
+            && config.drop_probability == 0.0
+            && config.corrupt_probability == 0.0;
+
+        if is_perfect {
+            return None;
+        }
+
+        Some(Wire {
+            latency: config.latency,
+            bandwidth: config.bandwidth,
+            drop_probability: config.drop_probability,
+            corrupt_probability: config.corrupt_probability,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            busy_until: Instant::now(),
+        })
+    }
+
+    /// Sends the bytes over the wire, possibly corrupting them, and returns when they will arrive.
+    /// Returns `None` if they are lost in transit.
+    fn transmit(&mut self, bytes: &mut [u8]) -> Option<Instant> {
+        let serialization = self.bandwidth.map_or(Duration::ZERO, |bandwidth| {
+            Duration::from_secs_f64(bytes.len() as f64 / bandwidth as f64)
+        });
+        self.busy_until = self.busy_until.max(Instant::now()) + serialization;
+
+        if self.rng.gen_bool(self.drop_probability) {
+            return None;
+        }
+
+        if !bytes.is_empty() && self.rng.gen_bool(self.corrupt_probability) {
+            let idx = self.rng.gen_range(0..bytes.len());
+            bytes[idx] ^= 1 << self.rng.gen_range(0..8);
+        }
+
+        Some(self.busy_until + self.latency)
+    }
+}
 
-#[derive(Default)]
-// TODO buffer full
 struct Buffer {
     buf: VecDeque<u8>,
+    /// Lengths of the frames in `buf`, in order. Only used if the link is framed.
+    frames: VecDeque<usize>,
+    /// Writes which are yet to arrive, in order of arrival.
+    in_transit: VecDeque<(Instant, Vec<u8>)>,
+    in_transit_len: usize,
+    wire: Option<Wire>,
+    capacity: usize,
+    max_frame_size: Option<usize>,
     on_send: Event,
+    on_recv: Event,
+}
+
+impl Buffer {
+    fn new(capacity: usize, max_frame_size: Option<usize>, wire: Option<Wire>) -> Buffer {
+        Buffer {
+            buf: VecDeque::with_capacity(capacity),
+            frames: VecDeque::new(),
+            in_transit: VecDeque::new(),
+            in_transit_len: 0,
+            wire,
+            capacity,
+            max_frame_size,
+            on_send: Event::new(),
+            on_recv: Event::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.buf.len() + self.in_transit_len
+    }
+
+    fn has_space(&self) -> bool {
+        match self.max_frame_size {
+            // Only ready once a frame of any size will fit
+            Some(max_frame_size) => self.len() + max_frame_size <= self.capacity,
+            None => self.len() < self.capacity,
+        }
+    }
+
+    /// Moves writes which have arrived by now into the readable buffer.
+    fn deliver(&mut self) {
+        let now = Instant::now();
+
+        while let Some((arrival, _)) = self.in_transit.front() {
+            if *arrival > now {
+                break;
+            }
+
+            let (_, bytes) = self.in_transit.pop_front().unwrap();
+            self.in_transit_len -= bytes.len();
+            self.arrive(bytes);
+        }
+    }
+
+    fn arrive(&mut self, bytes: Vec<u8>) {
+        if self.max_frame_size.is_some() {
+            self.frames.push_back(bytes.len());
+        }
+
+        self.buf.extend(bytes);
+    }
+
+    fn is_ready_for_read(&mut self) -> bool {
+        self.deliver();
+        !self.buf.is_empty()
+    }
+
+    /// Waits until bytes can be read, whether they are yet to be sent or are already in transit.
+    fn wait_until_ready_for_read(&mut self) -> impl Future<Output = ()> + Unpin {
+        let listener = self.on_send.listen();
+
+        if self.is_ready_for_read() {
+            return Either::Right(futures::future::ready(()));
+        }
+
+        // Bytes still in transit become readable without anyone sending more
+        let arrival = match self.in_transit.front() {
+            Some((arrival, _)) => {
+                Either::Left(Box::pin(tokio::time::sleep_until((*arrival).into())))
+            }
+            None => Either::Right(futures::future::pending()),
+        };
+
+        Either::Left(futures::future::select(listener, arrival).map(|_| ()))
+    }
+
+    /// Number of bytes the next read can return.
+    fn num_ready_bytes(&mut self) -> usize {
+        self.deliver();
+
+        match self.max_frame_size {
+            Some(_) => self.frames.front().copied().unwrap_or(0),
+            None => self.buf.len(),
+        }
+    }
+
+    /// Reads as many bytes as are available, waking any writers waiting for space. If the link is
+    /// framed, only the next frame is read, and any part of it which does not fit is discarded.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> usize {
+        self.deliver();
+
+        let n = match self.max_frame_size {
+            Some(_) => match self.frames.pop_front() {
+                Some(frame_len) => {
+                    let mut frame = self.buf.drain(..frame_len);
+                    let mut n = 0;
+
+                    for buf in bufs.iter_mut() {
+                        for (dst, src) in buf.iter_mut().zip(frame.by_ref()) {
+                            *dst = src;
+                            n += 1;
+                        }
+                    }
+
+                    n
+                }
+                None => 0,
+            },
+            None => self.buf.read_vectored(bufs).unwrap(),
+        };
+
+        if n > 0 {
+            self.on_recv.notify(usize::MAX);
+        }
+
+        n
+    }
+
+    /// Writes as many bytes as fit, returning how many were written. This is a short write if the
+    /// buffer is close to full, and writes nothing if the buffer is full. If the link is framed,
+    /// the write is either delivered whole as one frame or not at all.
+    ///
+    /// Bytes lost in transit still count as written.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> usize {
+        let space = self.capacity.saturating_sub(self.len());
+        let len: usize = bufs.iter().map(|buf| buf.len()).sum();
+
+        let len = match self.max_frame_size {
+            Some(_) if len > space => 0,
+            Some(_) => len,
+            None => len.min(space),
+        };
+
+        if len == 0 {
+            return 0;
+        }
+
+        let mut bytes: Vec<u8> = bufs
+            .iter()
+            .flat_map(|buf| buf.iter().copied())
+            .take(len)
+            .collect();
+
+        match &mut self.wire {
+            Some(wire) => match wire.transmit(&mut bytes) {
+                Some(arrival) => {
+                    self.in_transit_len += bytes.len();
+                    self.in_transit.push_back((arrival, bytes));
+                }
+                None => return len,
+            },
+            None => self.arrive(bytes),
+        }
+
+        self.on_send.notify(usize::MAX);
+        len
+    }
 }
 
-#[derive(Default)]
 struct DuplexLink {
     duplex_bufs: [Mutex<Buffer>; 2],
+    capture: Mutex<Option<Capture>>,
 }
 
 #[derive(Clone)]
@@ -26,7 +318,41 @@ pub struct AttachedDuplexLink {
 
 impl AttachedDuplexLink {
     pub fn new_pair() -> (AttachedDuplexLink, AttachedDuplexLink) {
-        let shared = Arc::new(DuplexLink::default());
+        Self::new_pair_with_config(LinkConfig::default())
+    }
+
+    /// # Panics
+    /// Panics if the link's capacity is zero, if it is framed and its maximum frame size exceeds
+    /// its capacity, if its bandwidth is zero, or if either probability is not between 0 and 1.
+    pub fn new_pair_with_config(config: LinkConfig) -> (AttachedDuplexLink, AttachedDuplexLink) {
+        assert!(config.capacity != 0, "capacity must not be zero");
+        assert!(
+            config.max_frame_size.unwrap_or(0) <= config.capacity,
+            "maximum frame size exceeds link capacity"
+        );
+        assert!(config.bandwidth != Some(0), "bandwidth must not be zero");
+        assert!(
+            (0.0..=1.0).contains(&config.drop_probability),
+            "drop probability must be between 0 and 1"
+        );
+        assert!(
+            (0.0..=1.0).contains(&config.corrupt_probability),
+            "corrupt probability must be between 0 and 1"
+        );
+
+        // Each direction gets its own wire, so derive a distinct seed for the second
+        let new_buf = |seed: Option<u64>| {
+            let wire = Wire::new(&config, seed);
+            Mutex::new(Buffer::new(config.capacity, config.max_frame_size, wire))
+        };
+
+        let shared = Arc::new(DuplexLink {
+            duplex_bufs: [
+                new_buf(config.seed),
+                new_buf(config.seed.map(|seed| seed.wrapping_add(1))),
+            ],
+            capture: Mutex::new(None),
+        });
 
         let first = AttachedDuplexLink {
             first_half: true,
@@ -40,11 +366,21 @@ impl AttachedDuplexLink {
         (first, second)
     }
 
-    fn read_buf(&self) -> MutexGuard<'_, Buffer> {
-        if self.first_half {
-            self.shared.duplex_bufs[1].lock().unwrap()
-        } else {
-            self.shared.duplex_bufs[0].lock().unwrap()
+    /// Starts recording everything sent over the link in either direction to a pcap file at the
+    /// given path, replacing any capture already in progress.
+    pub fn start_capture(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let framed = self.write_buf().max_frame_size.is_some();
+        let capture = Capture::create(path.as_ref(), framed)?;
+        *self.shared.capture.lock().unwrap() = Some(capture);
+        Ok(())
+    }
+
+    /// Stops recording, writing out the rest of the capture. Returns the first error writing to the
+    /// pcap file, after which nothing more was recorded.
+    pub fn stop_capture(&self) -> io::Result<()> {
+        match self.shared.capture.lock().unwrap().take() {
+            Some(capture) => capture.finish(),
+            None => Ok(()),
         }
     }
 
@@ -57,51 +393,526 @@ impl AttachedDuplexLink {
     }
 }
 
+/// A device made outside of this crate, such as a sensor or a printer, which can be plugged into a
+/// computer with [`Devices::add_device`]. It appears to the guest as a character device named
+/// `/dev/<name>`, and can be waited on like any other device.
+///
+/// Reads and writes must never block. A read with nothing waiting should return no bytes, and a
+/// write which does not fit should write none, which the guest sees as `EAGAIN`.
+pub trait Device: Send + Sync {
+    /// Name of the device's file in `/dev`.
+    fn name(&self) -> &str;
+
+    /// Called each time the guest opens the device, but not when it only looks up the device's
+    /// filestat. Returning an error fails the open.
+    fn open(&self, read: bool, write: bool) -> Result<(), wasi_common::Error> {
+        let _ = (read, write);
+        Ok(())
+    }
+
+    /// Reads bytes from the device, returning how many were read. Reading nothing into non-empty
+    /// buffers tells the guest to try again later, as devices never reach the end of their file.
+    fn read(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize, wasi_common::Error> {
+        let _ = bufs;
+        Err(wasi_common::Error::badf().context("device cannot be read"))
+    }
+
+    /// Writes bytes to the device, returning how many were written. Writing nothing from non-empty
+    /// buffers tells the guest to try again later.
+    fn write(&self, bufs: &[IoSlice<'_>]) -> Result<usize, wasi_common::Error> {
+        let _ = bufs;
+        Err(wasi_common::Error::badf().context("device cannot be written"))
+    }
+
+    /// Number of bytes the next read can return.
+    fn num_ready_bytes(&self) -> usize {
+        0
+    }
+
+    fn is_ready_for_read(&self) -> bool {
+        self.num_ready_bytes() > 0
+    }
+
+    fn is_ready_for_write(&self) -> bool {
+        true
+    }
+
+    /// Completes when the device may have become ready for read. This is called before checking
+    /// whether the device is ready, so it must complete if the device becomes ready in between.
+    fn listen_for_read(&self) -> BoxFuture<'static, ()> {
+        futures::future::pending().boxed()
+    }
+
+    /// Completes when the device may have become ready for write, like
+    /// [`listen_for_read`](Device::listen_for_read).
+    fn listen_for_write(&self) -> BoxFuture<'static, ()> {
+        futures::future::pending().boxed()
+    }
+
+    /// Fills in the device's filestat, such as its size or timestamps. It comes with the device
+    /// number and type already filled in, which should be left as they are.
+    fn filestat(&self, filestat: &mut Filestat) {
+        let _ = filestat;
+    }
+}
+
+/// A character device backed by a buffer of received bytes, such as one end of a network link, as
+/// seen by the computer it is attached to.
+trait CharDevice: Send + Sync {
+    /// Buffer of bytes received by the device.
+    fn read_buf(&self) -> MutexGuard<'_, Buffer>;
+
+    /// Sends bytes from the device, returning how many were sent.
+    fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> usize;
+
+    fn max_frame_size(&self) -> Option<usize>;
+
+    fn is_ready_for_write(&self) -> bool;
+
+    /// Listens for space being freed up for writing. `None` if the device's write readiness never
+    /// changes.
+    fn listen_for_write(&self) -> Option<EventListener>;
+}
+
+impl CharDevice for AttachedDuplexLink {
+    fn read_buf(&self) -> MutexGuard<'_, Buffer> {
+        if self.first_half {
+            self.shared.duplex_bufs[1].lock().unwrap()
+        } else {
+            self.shared.duplex_bufs[0].lock().unwrap()
+        }
+    }
+
+    fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> usize {
+        // Hold the buffer while recording, so that packets are captured in the order they are sent.
+        // Recording only queues the packet, so the file is never written to while holding it.
+        let mut buf = self.write_buf();
+        let n = buf.write_vectored(bufs);
+
+        if let (Some(capture), true) = (&*self.shared.capture.lock().unwrap(), n > 0) {
+            capture.record(bufs, n);
+        }
+
+        n
+    }
+
+    fn max_frame_size(&self) -> Option<usize> {
+        self.write_buf().max_frame_size
+    }
+
+    fn is_ready_for_write(&self) -> bool {
+        self.write_buf().has_space()
+    }
+
+    fn listen_for_write(&self) -> Option<EventListener> {
+        Some(self.write_buf().on_recv.listen())
+    }
+}
+
+/// Whether a device is still plugged into its computer. Shared with every fd open to the device.
+#[derive(Default)]
+struct Plug {
+    unplugged: AtomicBool,
+    on_unplug: Event,
+}
+
+impl Plug {
+    fn is_unplugged(&self) -> bool {
+        self.unplugged.load(Ordering::Acquire)
+    }
+
+    fn unplug(&self) {
+        self.unplugged.store(true, Ordering::Release);
+        self.on_unplug.notify(usize::MAX);
+    }
+}
+
+struct Plugged<T> {
+    device: T,
+    plug: Arc<Plug>,
+}
+
+impl<T> Plugged<T> {
+    fn new(device: T) -> Plugged<T> {
+        Plugged {
+            device,
+            plug: Arc::new(Plug::default()),
+        }
+    }
+}
+
+/// The devices plugged into a computer. Each device is identified by its type and index, which
+/// stays the same for as long as the device is plugged in. Indices are never reused, so an fd open
+/// to an unplugged device can never refer to a newer device. The exception is `/dev/events`, whose
+/// queues only live as long as their fds, so their indices are reused once closed.
 #[derive(Default)]
 pub struct Devices {
-    ethernet_links: Vec<AttachedDuplexLink>,
-    wireless_links: Vec<AttachedDuplexLink>,
+    ethernet_links: BTreeMap<usize, Plugged<AttachedDuplexLink>>,
+    wireless_links: BTreeMap<usize, Plugged<AttachedRadio>>,
+    event_queues: BTreeMap<usize, Plugged<Weak<EventQueue>>>,
+    custom_devices: BTreeMap<usize, Plugged<Arc<dyn Device>>>,
+    numbers: DeviceNumbers,
+    stdin: Arc<Stdin>,
+    tty: Arc<Tty>,
+    framebuffer: Arc<Framebuffer>,
+    keyboard: Arc<InputDevice>,
+    mouse: Arc<InputDevice>,
+    random: Arc<RandomSource>,
+    next_ethernet_idx: usize,
+    next_wireless_idx: usize,
+    next_custom_idx: usize,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DeviceType {
     Ethernet,
     Wireless,
+    /// An fd open to `/dev/events`. Each fd has its own queue.
+    Events,
+    /// The computer's stdin, which is always index 0.
+    Stdin,
+    /// The computer's terminal, `/dev/tty0`.
+    Tty,
+    /// The computer's keyboard at index [`INPUT_KEYBOARD`], and its mouse at [`INPUT_MOUSE`].
+    Input,
+    /// A [`Device`] plugged in by the host.
+    Custom,
 }
 
+pub const INPUT_KEYBOARD: usize = 0;
+pub const INPUT_MOUSE: usize = 1;
+
 impl Devices {
-    pub fn add_ethernet(&mut self, link: AttachedDuplexLink) {
-        self.ethernet_links.push(link);
+    /// Plugs in the link, returning its index.
+    ///
+    /// # Panics
+    /// Panics if every index a link can have has been used.
+    pub fn add_ethernet(&mut self, link: AttachedDuplexLink) -> usize {
+        let idx = next_idx(&mut self.next_ethernet_idx).expect("ran out of ethernet indices");
+        self.ethernet_links.insert(idx, Plugged::new(link));
+        self.publish_event(DEVICE_EVENT_PLUGGED, DeviceType::Ethernet, idx);
+        idx
+    }
+
+    /// Plugs in the radio as-is, returning its index. Prefer
+    /// [`Computer::add_wireless`](crate::Computer::add_wireless), which also places the radio at
+    /// the computer's position.
+    ///
+    /// # Panics
+    /// Panics if every index a radio can have has been used.
+    pub fn add_wireless(&mut self, radio: AttachedRadio) -> usize {
+        let idx = next_idx(&mut self.next_wireless_idx).expect("ran out of wireless indices");
+        self.wireless_links.insert(idx, Plugged::new(radio));
+        self.publish_event(DEVICE_EVENT_PLUGGED, DeviceType::Wireless, idx);
+        idx
+    }
+
+    /// Unplugs the link. Any fds open to it will fail from now on.
+    pub fn remove_ethernet(&mut self, idx: usize) -> Option<AttachedDuplexLink> {
+        let plugged = self.ethernet_links.remove(&idx)?;
+        plugged.plug.unplug();
+        self.publish_event(DEVICE_EVENT_UNPLUGGED, DeviceType::Ethernet, idx);
+        Some(plugged.device)
+    }
+
+    /// Unplugs the radio. Any fds open to it will fail from now on.
+    pub fn remove_wireless(&mut self, idx: usize) -> Option<AttachedRadio> {
+        let plugged = self.wireless_links.remove(&idx)?;
+        plugged.plug.unplug();
+        self.publish_event(DEVICE_EVENT_UNPLUGGED, DeviceType::Wireless, idx);
+        Some(plugged.device)
+    }
+
+    /// Plugs in a device made outside of this crate, returning its index.
+    ///
+    /// # Panics
+    /// Panics if the device's name is not a valid file name, or is already taken in `/dev`, or if
+    /// every index a device can have has been used.
+    pub fn add_device(&mut self, device: Arc<dyn Device>) -> usize {
+        let name = device.name();
+        assert!(
+            !name.is_empty() && !name.contains('/') && name != "." && name != "..",
+            "invalid device name {name:?}"
+        );
+        assert!(
+            !virtual_fs::is_reserved_name(name) && self.custom_device(name).is_none(),
+            "device name {name:?} is already taken"
+        );
+
+        let idx = next_idx(&mut self.next_custom_idx).expect("ran out of device indices");
+        self.custom_devices.insert(idx, Plugged::new(device));
+        idx
+    }
+
+    /// Unplugs the device. Any fds open to it will fail from now on.
+    pub fn remove_device(&mut self, idx: usize) -> Option<Arc<dyn Device>> {
+        let plugged = self.custom_devices.remove(&idx)?;
+        plugged.plug.unplug();
+        Some(plugged.device)
+    }
+
+    /// The computer's stdin, which input can be pushed into while the computer runs.
+    pub fn stdin(&self) -> Arc<Stdin> {
+        self.stdin.clone()
+    }
+
+    /// The computer's terminal, whose screen can be read while the computer runs.
+    pub fn tty(&self) -> Arc<Tty> {
+        self.tty.clone()
     }
 
-    fn device(&self, dev_type: DeviceType, dev_idx: usize) -> Option<&AttachedDuplexLink> {
+    /// The computer's display, whose frames can be grabbed while the computer runs.
+    pub fn framebuffer(&self) -> Arc<Framebuffer> {
+        self.framebuffer.clone()
+    }
+
+    /// The computer's keyboard, which key events can be injected into while the computer runs.
+    pub fn keyboard(&self) -> Arc<InputDevice> {
+        self.keyboard.clone()
+    }
+
+    /// The computer's mouse, which pointer events can be injected into while the computer runs.
+    pub fn mouse(&self) -> Arc<InputDevice> {
+        self.mouse.clone()
+    }
+
+    /// Seeds the source of `/dev/random` and `/dev/urandom`, so that the bytes read from them are
+    /// the same every run.
+    pub fn seed_random(&self, seed: u64) {
+        self.random.reseed(seed);
+    }
+
+    pub(crate) fn device_numbers(&self) -> &DeviceNumbers {
+        &self.numbers
+    }
+
+    /// The device a device number from a filestat belongs to, or `None` if it is not a device
+    /// which can be waited on, such as a regular file.
+    pub fn decompose_device(&self, device_no: u64) -> Option<(DeviceType, usize)> {
+        self.numbers.decompose(device_no)
+    }
+
+    pub(crate) fn move_radios(&self, position: Position) {
+        for radio in self.wireless_links.values() {
+            radio.device.set_position(position);
+        }
+    }
+
+    fn device(
+        &self,
+        dev_type: DeviceType,
+        dev_idx: usize,
+    ) -> Option<(Arc<dyn CharDevice>, Arc<Plug>)> {
+        match dev_type {
+            DeviceType::Ethernet => self.ethernet_links.get(&dev_idx).map(|link| {
+                let device = Arc::new(link.device.clone()) as Arc<dyn CharDevice>;
+                (device, link.plug.clone())
+            }),
+            DeviceType::Wireless => self.wireless_links.get(&dev_idx).map(|radio| {
+                let device = Arc::new(radio.device.clone()) as Arc<dyn CharDevice>;
+                (device, radio.plug.clone())
+            }),
+            DeviceType::Events => self.event_queues.get(&dev_idx).and_then(|queue| {
+                let device = queue.device.upgrade()? as Arc<dyn CharDevice>;
+                Some((device, queue.plug.clone()))
+            }),
+            DeviceType::Stdin => (dev_idx == 0).then(|| {
+                let device = self.stdin.clone() as Arc<dyn CharDevice>;
+                (device, self.stdin.plug())
+            }),
+            DeviceType::Tty => (dev_idx == 0).then(|| {
+                let device = self.tty.clone() as Arc<dyn CharDevice>;
+                (device, Arc::new(Plug::default()))
+            }),
+            DeviceType::Input => {
+                let device = match dev_idx {
+                    INPUT_KEYBOARD => self.keyboard.clone(),
+                    INPUT_MOUSE => self.mouse.clone(),
+                    _ => return None,
+                };
+                Some((device as Arc<dyn CharDevice>, Arc::new(Plug::default())))
+            }
+            // Not backed by a buffer, so looked up separately
+            DeviceType::Custom => None,
+        }
+    }
+
+    /// Looks up a device plugged in by the host by name, returning its index, the device and its
+    /// plug.
+    fn custom_device(&self, name: &str) -> Option<(usize, Arc<dyn Device>, Arc<Plug>)> {
+        self.custom_devices
+            .iter()
+            .find(|(_idx, plugged)| plugged.device.name() == name)
+            .map(|(idx, plugged)| (*idx, plugged.device.clone(), plugged.plug.clone()))
+    }
+
+    /// Opens a new queue of plug and unplug events, returning its index and the queue, or `None` if
+    /// as many queues are open as there are minors. The queue only lives for as long as the
+    /// returned handle, after which its index is free to be reused.
+    fn subscribe_events(&mut self) -> Option<(usize, Arc<EventQueue>)> {
+        self.event_queues
+            .retain(|_idx, queue| queue.device.strong_count() > 0);
+
+        // The lowest free index, which is the first one not matching its position
+        let idx = self
+            .event_queues
+            .keys()
+            .zip(0..)
+            .find(|(idx, expected)| **idx != *expected)
+            .map_or(self.event_queues.len(), |(_idx, expected)| expected);
+        if idx >= MINOR_COUNT {
+            return None;
+        }
+
+        let queue = Arc::new(EventQueue::new());
+        self.event_queues
+            .insert(idx, Plugged::new(Arc::downgrade(&queue)));
+        Some((idx, queue))
+    }
+
+    fn publish_event(&mut self, kind: u32, dev_type: DeviceType, dev_idx: usize) {
+        let event = DeviceEvent {
+            kind,
+            device_type: match dev_type {
+                DeviceType::Ethernet => DEVICE_TYPE_ETHERNET,
+                DeviceType::Wireless => DEVICE_TYPE_WIRELESS,
+                DeviceType::Events
+                | DeviceType::Stdin
+                | DeviceType::Tty
+                | DeviceType::Input
+                | DeviceType::Custom => {
+                    unreachable!("only ethernet and wireless devices are plugged in")
+                }
+            },
+            device_idx: dev_idx as u32,
+        };
+
+        self.event_queues
+            .retain(|_idx, queue| match queue.device.upgrade() {
+                Some(queue) => {
+                    queue.push(event);
+                    true
+                }
+                None => false,
+            });
+    }
+
+    /// Indices of the plugged in devices of the given type, in ascending order.
+    fn indices(&self, dev_type: DeviceType) -> Vec<usize> {
         match dev_type {
-            DeviceType::Ethernet => self.ethernet_links.get(dev_idx),
-            DeviceType::Wireless => self.wireless_links.get(dev_idx),
+            DeviceType::Ethernet => self.ethernet_links.keys().copied().collect(),
+            DeviceType::Wireless => self.wireless_links.keys().copied().collect(),
+            DeviceType::Events => self.event_queues.keys().copied().collect(),
+            DeviceType::Stdin | DeviceType::Tty => vec![0],
+            DeviceType::Input => vec![INPUT_KEYBOARD, INPUT_MOUSE],
+            DeviceType::Custom => self.custom_devices.keys().copied().collect(),
         }
     }
 
     pub fn contains(&self, dev_type: DeviceType, dev_idx: usize) -> bool {
-        self.device(dev_type, dev_idx).is_some()
+        match dev_type {
+            DeviceType::Custom => self.custom_devices.contains_key(&dev_idx),
+            _ => self.device(dev_type, dev_idx).is_some(),
+        }
     }
 
     pub fn is_ready_for_read(&self, dev_type: DeviceType, dev_idx: usize) -> Option<bool> {
+        if dev_type == DeviceType::Custom {
+            let custom = self.custom_devices.get(&dev_idx)?;
+            return Some(custom.plug.is_unplugged() || custom.device.is_ready_for_read());
+        }
+
         self.device(dev_type, dev_idx)
-            .map(|dev| !dev.read_buf().buf.is_empty())
+            .map(|(dev, plug)| plug.is_unplugged() || dev.read_buf().is_ready_for_read())
     }
 
+    /// Waits until the device is ready for read, or is unplugged.
     pub fn wait_until_ready_for_read(
         &self,
         dev_type: DeviceType,
         dev_idx: usize,
     ) -> Option<impl Future<Output = ()> + Unpin> {
-        let dev = self.device(dev_type, dev_idx)?;
-        let listener = dev.read_buf().on_send.listen();
+        let (ready, unplugged) = if dev_type == DeviceType::Custom {
+            let custom = self.custom_devices.get(&dev_idx)?;
+            let unplugged = custom.plug.on_unplug.listen();
+            let listener = custom.device.listen_for_read();
 
-        Some(if dev.read_buf().buf.is_empty() {
-            Either::Left(listener)
+            if custom.plug.is_unplugged() || custom.device.is_ready_for_read() {
+                return Some(Either::Right(futures::future::ready(())));
+            }
+
+            (Either::Right(listener), unplugged)
         } else {
-            Either::Right(futures::future::ready(()))
-        })
+            let (dev, plug) = self.device(dev_type, dev_idx)?;
+            let unplugged = plug.on_unplug.listen();
+
+            if plug.is_unplugged() {
+                return Some(Either::Right(futures::future::ready(())));
+            }
+
+            let ready = dev.read_buf().wait_until_ready_for_read();
+            (Either::Left(ready), unplugged)
+        };
+
+        Some(Either::Left(
+            futures::future::select(ready, unplugged).map(|_| ()),
+        ))
+    }
+
+    pub fn is_ready_for_write(&self, dev_type: DeviceType, dev_idx: usize) -> Option<bool> {
+        if dev_type == DeviceType::Custom {
+            let custom = self.custom_devices.get(&dev_idx)?;
+            return Some(custom.device.is_ready_for_write());
+        }
+
+        self.device(dev_type, dev_idx)
+            .map(|(dev, _plug)| dev.is_ready_for_write())
+    }
+
+    /// Waits until the device is ready for write, or is unplugged.
+    pub fn wait_until_ready_for_write(
+        &self,
+        dev_type: DeviceType,
+        dev_idx: usize,
+    ) -> Option<impl Future<Output = ()> + Unpin> {
+        let (ready, unplugged) = if dev_type == DeviceType::Custom {
+            let custom = self.custom_devices.get(&dev_idx)?;
+            let unplugged = custom.plug.on_unplug.listen();
+            let listener = custom.device.listen_for_write();
+
+            if custom.device.is_ready_for_write() {
+                return Some(Either::Right(futures::future::ready(())));
+            }
+
+            (Either::Right(listener), unplugged)
+        } else {
+            let (dev, plug) = self.device(dev_type, dev_idx)?;
+            let unplugged = plug.on_unplug.listen();
+            let listener = dev.listen_for_write();
+
+            if dev.is_ready_for_write() {
+                return Some(Either::Right(futures::future::ready(())));
+            }
+
+            let ready = match listener {
+                Some(listener) => Either::Left(listener),
+                // Never becomes writable, so can only wait to be unplugged
+                None => Either::Right(futures::future::pending()),
+            };
+            (Either::Left(ready), unplugged)
+        };
+
+        Some(Either::Left(
+            futures::future::select(ready, unplugged).map(|_| ()),
+        ))
     }
 }
+
+/// Takes the next index from a class's counter, or `None` if its minors have run out.
+fn next_idx(next: &mut usize) -> Option<usize> {
+    let idx = *next;
+    (idx < MINOR_COUNT).then(|| {
+        *next += 1;
+        idx
+    })
+}
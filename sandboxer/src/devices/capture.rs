@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{self, BufWriter, IoSlice, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Link type for byte stream links, whose chunks have no particular format.
+const LINKTYPE_USER0: u32 = 147;
+/// Link type for framed links. Their frames have no particular format either, as those relayed by
+/// a [`Switch`](super::switch::Switch) start with its own header rather than an Ethernet one.
+const LINKTYPE_USER1: u32 = 148;
+
+const SNAPLEN: u32 = 65535;
+
+/// How often recorded packets are written out, so that the capture can be followed live without
+/// writing to the file on every packet.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of packets recorded but not yet written out, beyond which further packets are left out
+/// of the capture, as a real capture drops packets it cannot keep up with.
+const QUEUE_LEN: usize = 4096;
+
+/// Writes everything sent over a link to a pcap file, which can be opened in Wireshark. Each write
+/// to the link is recorded as one packet, timestamped with when it was sent. Packets are written
+/// out on a thread of their own, so that recording never waits on the file, and are flushed
+/// periodically, and when the capture is finished or dropped.
+pub(super) struct Capture {
+    packets: SyncSender<Packet>,
+    /// Returns the first error writing to the file, after which nothing more is written.
+    writer: JoinHandle<io::Result<()>>,
+}
+
+struct Packet {
+    time: SystemTime,
+    /// Length of the packet as sent, of which only up to [`SNAPLEN`] bytes are kept.
+    len: usize,
+    data: Vec<u8>,
+}
+
+impl Capture {
+    pub(super) fn create(path: &Path, framed: bool) -> io::Result<Capture> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let link_type = if framed {
+            LINKTYPE_USER1
+        } else {
+            LINKTYPE_USER0
+        };
+
+        out.write_all(&0xa1b2c3d4u32.to_le_bytes())?; // Magic number, microsecond timestamps
+        out.write_all(&2u16.to_le_bytes())?; // Major version
+        out.write_all(&4u16.to_le_bytes())?; // Minor version
+        out.write_all(&0i32.to_le_bytes())?; // Timezone offset
+        out.write_all(&0u32.to_le_bytes())?; // Timestamp accuracy
+        out.write_all(&SNAPLEN.to_le_bytes())?;
+        out.write_all(&link_type.to_le_bytes())?;
+        out.flush()?;
+
+        let (packets, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        let writer = thread::Builder::new()
+            .name(String::from("link capture"))
+            .spawn(move || write_packets(out, receiver))?;
+
+        Ok(Capture { packets, writer })
+    }
+
+    /// Records the first `len` bytes of the given buffers as one packet.
+    pub(super) fn record(&self, bufs: &[IoSlice<'_>], len: usize) {
+        let mut remaining = len.min(SNAPLEN as usize);
+        let mut data = Vec::with_capacity(remaining);
+        for buf in bufs {
+            let n = buf.len().min(remaining);
+            data.extend_from_slice(&buf[..n]);
+            remaining -= n;
+        }
+
+        // Fails if the writer is too far behind, or has stopped after an error
+        let _ = self.packets.try_send(Packet {
+            time: SystemTime::now(),
+            len,
+            data,
+        });
+    }
+
+    /// Writes out everything recorded, returning the first error writing to the file.
+    pub(super) fn finish(self) -> io::Result<()> {
+        drop(self.packets);
+        self.writer
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+/// Writes packets out until every sender is dropped, flushing whenever none have arrived for a
+/// while, or the last flush was long enough ago.
+fn write_packets(mut out: BufWriter<File>, packets: Receiver<Packet>) -> io::Result<()> {
+    let mut last_flush = Instant::now();
+
+    loop {
+        match packets.recv_timeout(FLUSH_INTERVAL) {
+            Ok(packet) => write_packet(&mut out, &packet)?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return out.flush(),
+        }
+
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            out.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+}
+
+fn write_packet(out: &mut impl Write, packet: &Packet) -> io::Result<()> {
+    let since_epoch = packet.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    out.write_all(&(since_epoch.as_secs() as u32).to_le_bytes())?;
+    out.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+    out.write_all(&(packet.data.len() as u32).to_le_bytes())?;
+    out.write_all(&(packet.len as u32).to_le_bytes())?;
+    out.write_all(&packet.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_packets_in_order() {
+        let path = std::env::temp_dir().join(format!("capture-{}.pcap", uuid::Uuid::new_v4()));
+        let capture = Capture::create(&path, true).unwrap();
+        capture.record(&[IoSlice::new(b"first"), IoSlice::new(b" packet")], 12);
+        capture.record(&[IoSlice::new(b"second")], 3);
+        capture.finish().unwrap();
+
+        let pcap = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(pcap[20..24], LINKTYPE_USER1.to_le_bytes());
+        let first = &pcap[24..];
+        assert_eq!(first[8..16], [12, 0, 0, 0, 12, 0, 0, 0]);
+        assert_eq!(&first[16..28], b"first packet");
+        let second = &first[28..];
+        assert_eq!(second[8..16], [3, 0, 0, 0, 3, 0, 0, 0]);
+        assert_eq!(&second[16..], b"sec");
+    }
+}
@@ -0,0 +1,57 @@
+use crate::devices::{Buffer, CharDevice};
+use bytemuck::bytes_of;
+use event_listener::EventListener;
+use host_api_sys::DeviceEvent;
+use std::io::IoSlice;
+use std::sync::{Mutex, MutexGuard};
+
+/// Number of events each `/dev/events` fd buffers before further events are dropped.
+const EVENT_QUEUE_LEN: usize = 256;
+
+/// Plug and unplug events waiting to be read from one `/dev/events` fd. Each event is one frame.
+pub(super) struct EventQueue {
+    buf: Mutex<Buffer>,
+}
+
+impl EventQueue {
+    pub(super) fn new() -> EventQueue {
+        let event_size = std::mem::size_of::<DeviceEvent>();
+
+        EventQueue {
+            buf: Mutex::new(Buffer::new(
+                EVENT_QUEUE_LEN * event_size,
+                Some(event_size),
+                None,
+            )),
+        }
+    }
+
+    pub(super) fn push(&self, event: DeviceEvent) {
+        self.buf
+            .lock()
+            .unwrap()
+            .write_vectored(&[IoSlice::new(bytes_of(&event))]);
+    }
+}
+
+impl CharDevice for EventQueue {
+    fn read_buf(&self) -> MutexGuard<'_, Buffer> {
+        self.buf.lock().unwrap()
+    }
+
+    fn write_vectored(&self, _bufs: &[IoSlice<'_>]) -> usize {
+        0
+    }
+
+    fn max_frame_size(&self) -> Option<usize> {
+        Some(std::mem::size_of::<DeviceEvent>())
+    }
+
+    fn is_ready_for_write(&self) -> bool {
+        false
+    }
+
+    fn listen_for_write(&self) -> Option<EventListener> {
+        None
+    }
+}
@@ -0,0 +1,310 @@
+use crate::devices::virtual_fs::DeviceNode;
+use async_trait::async_trait;
+use host_api_sys::{FramebufferInfo, PIXEL_FORMAT_RGBA8888};
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::snapshots::preview_1::types::Errno;
+use wasi_common::{Error, ErrorExt, WasiFile};
+
+/// Width of a computer's display in pixels.
+pub const FB_WIDTH: u32 = 640;
+/// Height of a computer's display in pixels.
+pub const FB_HEIGHT: u32 = 480;
+
+const BYTES_PER_PIXEL: u32 = 4;
+/// Number of damage rectangles kept before they are merged into one covering them all.
+const MAX_DAMAGE_RECTS: usize = 64;
+
+/// An area of the display, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.x + other.width <= self.x + self.width
+            && other.y + other.height <= self.y + self.height
+    }
+
+    fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+/// A computer's display, drawn on by the guest through `/dev/fb0`. The framebuffer holds
+/// [`FB_WIDTH`] by [`FB_HEIGHT`] pixels, row by row from the top left, with each pixel stored as
+/// red, green, blue and alpha bytes. The display starts out opaque black.
+pub struct Framebuffer {
+    state: Mutex<FramebufferState>,
+}
+
+struct FramebufferState {
+    pixels: Vec<u8>,
+    /// Areas written to since damage was last taken.
+    damage: Vec<Rect>,
+}
+
+impl Framebuffer {
+    pub fn info(&self) -> FramebufferInfo {
+        FramebufferInfo {
+            width: FB_WIDTH,
+            height: FB_HEIGHT,
+            stride: FB_WIDTH * BYTES_PER_PIXEL,
+            pixel_format: PIXEL_FORMAT_RGBA8888,
+        }
+    }
+
+    /// The current frame as RGBA bytes.
+    pub fn frame(&self) -> Vec<u8> {
+        self.state.lock().unwrap().pixels.clone()
+    }
+
+    /// Takes the areas which have been drawn on since damage was last taken. Take the damage before
+    /// grabbing the frame, so that anything drawn in between is redrawn next time rather than lost.
+    pub fn take_damage(&self) -> Vec<Rect> {
+        std::mem::take(&mut self.state.lock().unwrap().damage)
+    }
+
+    pub(super) fn len() -> u64 {
+        (FB_WIDTH * FB_HEIGHT * BYTES_PER_PIXEL) as u64
+    }
+
+    fn read_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> usize {
+        let state = self.state.lock().unwrap();
+        let mut src = state.pixels.get(offset as usize..).unwrap_or_default();
+        let mut n = 0;
+
+        for buf in bufs {
+            let len = buf.len().min(src.len());
+            buf[..len].copy_from_slice(&src[..len]);
+            src = &src[len..];
+            n += len;
+        }
+
+        n
+    }
+
+    fn write_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let start = (offset as usize).min(state.pixels.len());
+        let mut dst = &mut state.pixels[start..];
+        let mut n = 0;
+
+        for buf in bufs {
+            let len = buf.len().min(dst.len());
+            dst[..len].copy_from_slice(&buf[..len]);
+            dst = &mut dst[len..];
+            n += len;
+        }
+
+        if n > 0 {
+            state.add_damage(start, start + n);
+        }
+
+        n
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        let pixels = [0, 0, 0, 0xff].repeat((FB_WIDTH * FB_HEIGHT) as usize);
+        let whole = Rect {
+            x: 0,
+            y: 0,
+            width: FB_WIDTH,
+            height: FB_HEIGHT,
+        };
+
+        Framebuffer {
+            state: Mutex::new(FramebufferState {
+                pixels,
+                damage: vec![whole],
+            }),
+        }
+    }
+}
+
+impl FramebufferState {
+    /// Records the pixels covering the given range of bytes as damaged.
+    fn add_damage(&mut self, start: usize, end: usize) {
+        let first = start as u32 / BYTES_PER_PIXEL;
+        let last = (end as u32 - 1) / BYTES_PER_PIXEL;
+        let (first_row, last_row) = (first / FB_WIDTH, last / FB_WIDTH);
+
+        let rect = if first_row == last_row {
+            Rect {
+                x: first % FB_WIDTH,
+                y: first_row,
+                width: last - first + 1,
+                height: 1,
+            }
+        } else {
+            Rect {
+                x: 0,
+                y: first_row,
+                width: FB_WIDTH,
+                height: last_row - first_row + 1,
+            }
+        };
+
+        if self.damage.iter().any(|damaged| damaged.contains(&rect)) {
+            return;
+        }
+
+        // Drawing a shape row by row extends the same rectangle downwards
+        if let Some(last) = self.damage.last_mut() {
+            if last.x == rect.x && last.width == rect.width && last.y + last.height == rect.y {
+                last.height += rect.height;
+                return;
+            }
+        }
+
+        self.damage.push(rect);
+
+        if self.damage.len() > MAX_DAMAGE_RECTS {
+            let bounds = self
+                .damage
+                .iter()
+                .skip(1)
+                .fold(self.damage[0], |bounds, rect| bounds.union(rect));
+            self.damage = vec![bounds];
+        }
+    }
+}
+
+/// An fd open to `/dev/fb0`. Unlike other devices, it can be seeked, and reads and writes act on
+/// the pixels at the fd's position.
+pub(super) struct OpenFramebufferFile {
+    framebuffer: Arc<Framebuffer>,
+    node: DeviceNode,
+    position: AtomicU64,
+    read: bool,
+    write: bool,
+}
+
+impl OpenFramebufferFile {
+    pub(super) fn new(
+        framebuffer: Arc<Framebuffer>,
+        node: DeviceNode,
+        read: bool,
+        write: bool,
+    ) -> OpenFramebufferFile {
+        OpenFramebufferFile {
+            framebuffer,
+            node,
+            position: AtomicU64::new(0),
+            read,
+            write,
+        }
+    }
+}
+
+#[async_trait]
+impl WasiFile for OpenFramebufferFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(self.node.filestat(Framebuffer::len()))
+    }
+
+    async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let position = self.position.load(Ordering::Acquire);
+        let n = self.read_vectored_at(bufs, position).await?;
+        self.position.store(position + n, Ordering::Release);
+        Ok(n)
+    }
+
+    async fn read_vectored_at<'a>(
+        &self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        if !self.read {
+            return Err(Error::badf().context("file opened as writeonly"));
+        }
+
+        Ok(self.framebuffer.read_at(bufs, offset) as u64)
+    }
+
+    async fn write_vectored<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        let position = self.position.load(Ordering::Acquire);
+        let n = self.write_vectored_at(bufs, position).await?;
+        self.position.store(position + n, Ordering::Release);
+        Ok(n)
+    }
+
+    async fn write_vectored_at<'a>(&self, bufs: &[IoSlice<'a>], offset: u64) -> Result<u64, Error> {
+        if !self.write {
+            return Err(Error::badf().context("file opened as readonly"));
+        }
+
+        let n = self.framebuffer.write_at(bufs, offset);
+
+        if n == 0 && bufs.iter().any(|buf| !buf.is_empty()) {
+            return Err(Error::from(Errno::Nospc).context("write past end of framebuffer"));
+        }
+
+        Ok(n as u64)
+    }
+
+    async fn seek(&self, pos: SeekFrom) -> Result<u64, Error> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self
+                .position
+                .load(Ordering::Acquire)
+                .checked_add_signed(delta),
+            SeekFrom::End(delta) => Framebuffer::len().checked_add_signed(delta),
+        }
+        .ok_or_else(|| Error::invalid_argument().context("seek before start of framebuffer"))?;
+
+        self.position.store(position, Ordering::Release);
+        Ok(position)
+    }
+
+    fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(Framebuffer::len().saturating_sub(self.position.load(Ordering::Acquire)))
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        if self.read {
+            Ok(())
+        } else {
+            Err(Error::badf().context("file opened as writeonly"))
+        }
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        if self.write {
+            Ok(())
+        } else {
+            Err(Error::badf().context("file opened as readonly"))
+        }
+    }
+}
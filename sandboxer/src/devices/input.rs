@@ -0,0 +1,113 @@
+use crate::devices::{Buffer, CharDevice};
+use bytemuck::bytes_of;
+use event_listener::EventListener;
+use host_api_sys::{
+    InputEvent, INPUT_EVENT_KEY, INPUT_EVENT_REL, INPUT_EVENT_SYN, INPUT_KEY_PRESSED,
+    INPUT_KEY_RELEASED, INPUT_REL_WHEEL, INPUT_REL_X, INPUT_REL_Y, INPUT_SYN_REPORT,
+};
+use std::io::IoSlice;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of events each input device buffers before further events are dropped.
+const INPUT_QUEUE_LEN: usize = 1024;
+
+/// A keyboard or mouse plugged into a computer, which the host injects input events into. Each
+/// change is reported as one or more events followed by a sync event, as with Linux evdev. Events
+/// are shared between every fd open to the device.
+pub struct InputDevice {
+    buf: Mutex<Buffer>,
+}
+
+impl InputDevice {
+    /// Reports a key or button being pressed or released. Codes are Linux key and button codes.
+    pub fn key(&self, code: u16, pressed: bool) {
+        let value = if pressed {
+            INPUT_KEY_PRESSED
+        } else {
+            INPUT_KEY_RELEASED
+        };
+        self.push(&[(INPUT_EVENT_KEY, code, value)]);
+    }
+
+    /// Reports the pointer moving by the given number of pixels.
+    pub fn move_pointer(&self, dx: i32, dy: i32) {
+        self.push(&[
+            (INPUT_EVENT_REL, INPUT_REL_X, dx),
+            (INPUT_EVENT_REL, INPUT_REL_Y, dy),
+        ]);
+    }
+
+    /// Reports the scroll wheel turning by the given number of notches, positive being away from
+    /// the player.
+    pub fn scroll(&self, notches: i32) {
+        self.push(&[(INPUT_EVENT_REL, INPUT_REL_WHEEL, notches)]);
+    }
+
+    /// Pushes the events, followed by a sync event, all at once so that none are dropped unless
+    /// they all are.
+    pub fn push(&self, events: &[(u16, u16, i32)]) {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let time_us = since_epoch.as_micros() as u64;
+
+        let sync = (INPUT_EVENT_SYN, INPUT_SYN_REPORT, 0);
+        let events: Vec<InputEvent> = events
+            .iter()
+            .chain(std::iter::once(&sync))
+            .map(|&(kind, code, value)| InputEvent {
+                time_us,
+                kind,
+                code,
+                value,
+            })
+            .collect();
+
+        let mut buf = self.buf.lock().unwrap();
+        let record_size = std::mem::size_of::<InputEvent>();
+        if buf.len() + events.len() * record_size > INPUT_QUEUE_LEN * record_size {
+            return;
+        }
+
+        for event in &events {
+            buf.write_vectored(&[IoSlice::new(bytes_of(event))]);
+        }
+    }
+}
+
+impl Default for InputDevice {
+    fn default() -> Self {
+        let event_size = std::mem::size_of::<InputEvent>();
+
+        InputDevice {
+            buf: Mutex::new(Buffer::new(
+                INPUT_QUEUE_LEN * event_size,
+                Some(event_size),
+                None,
+            )),
+        }
+    }
+}
+
+impl CharDevice for InputDevice {
+    fn read_buf(&self) -> MutexGuard<'_, Buffer> {
+        self.buf.lock().unwrap()
+    }
+
+    fn write_vectored(&self, _bufs: &[IoSlice<'_>]) -> usize {
+        0
+    }
+
+    fn max_frame_size(&self) -> Option<usize> {
+        Some(std::mem::size_of::<InputEvent>())
+    }
+
+    fn is_ready_for_write(&self) -> bool {
+        false
+    }
+
+    fn listen_for_write(&self) -> Option<EventListener> {
+        None
+    }
+}
@@ -0,0 +1,113 @@
+use crate::devices::DeviceType;
+
+/// Major given to the first class registered. Later classes count down from here, well above the
+/// majors Linux hands out.
+const FIRST_MAJOR: u16 = 511;
+const MAJOR_BITS: u32 = 12;
+const MINOR_BITS: u32 = 20;
+/// Number of minors in each class, which bounds how many of its devices can exist at once.
+pub(crate) const MINOR_COUNT: usize = 1 << MINOR_BITS;
+
+/// A computer's table of device numbers. Each class of device, such as ethernet links, is given
+/// its own major when it is registered, and each device within it is told apart by its minor. The
+/// table maps device numbers found in filestats back to the devices they belong to.
+pub(crate) struct DeviceNumbers {
+    classes: Vec<DeviceClass>,
+}
+
+struct DeviceClass {
+    name: &'static str,
+    major: u16,
+    /// Type the class's devices are looked up as when the guest waits on them, with their minor as
+    /// index. Files of classes without one, such as `/dev/fb0` or those on the host's filesystem,
+    /// are always ready.
+    dev_type: Option<DeviceType>,
+}
+
+impl DeviceNumbers {
+    /// Allocates a major for a new class of devices, and returns it.
+    pub(crate) fn register(&mut self, name: &'static str, dev_type: Option<DeviceType>) -> u16 {
+        assert!(
+            self.major(name).is_none(),
+            "device class {name} is already registered"
+        );
+
+        let major = FIRST_MAJOR
+            .checked_sub(self.classes.len() as u16)
+            .filter(|&major| major > 0)
+            .expect("ran out of device majors");
+
+        self.classes.push(DeviceClass {
+            name,
+            major,
+            dev_type,
+        });
+
+        major
+    }
+
+    pub(crate) fn major(&self, name: &str) -> Option<u16> {
+        self.classes
+            .iter()
+            .find(|class| class.name == name)
+            .map(|class| class.major)
+    }
+
+    /// Device number of the device with the given minor in a registered class.
+    ///
+    /// # Panics
+    /// Panics if the class is not registered, or the minor does not fit in a device number.
+    pub(crate) fn device_number(&self, name: &str, minor: u32) -> u32 {
+        match self.major(name) {
+            Some(major) => make_device_number(major, minor)
+                .unwrap_or_else(|| panic!("minor {minor} of {name} is too large")),
+            None => panic!("device class {name} is not registered"),
+        }
+    }
+
+    /// The device a device number belongs to, or `None` if it is not a device which can be waited
+    /// on.
+    pub(crate) fn decompose(&self, device_no: u64) -> Option<(DeviceType, usize)> {
+        let major = ((device_no >> MINOR_BITS) & ((1 << MAJOR_BITS) - 1)) as u16;
+        let minor = (device_no & ((1 << MINOR_BITS) - 1)) as usize;
+
+        let class = self.classes.iter().find(|class| class.major == major)?;
+        Some((class.dev_type?, minor))
+    }
+}
+
+impl Default for DeviceNumbers {
+    fn default() -> Self {
+        let mut numbers = DeviceNumbers {
+            classes: Vec::new(),
+        };
+
+        numbers.register("dev", None);
+        numbers.register("ethernet", Some(DeviceType::Ethernet));
+        numbers.register("wireless", Some(DeviceType::Wireless));
+        numbers.register("events", Some(DeviceType::Events));
+        numbers.register("stdin", Some(DeviceType::Stdin));
+        numbers.register("tty", Some(DeviceType::Tty));
+        numbers.register("fb", None);
+        numbers.register("input", Some(DeviceType::Input));
+        // Minors match those of the Linux memory devices
+        numbers.register("mem", None);
+        // A minor for each host device, so host files are never mistaken for simulated devices
+        numbers.register("host", None);
+        numbers.register("custom", Some(DeviceType::Custom));
+
+        numbers
+    }
+}
+
+/// Device number made of the major and minor, or `None` if the minor is too large to fit.
+pub(crate) fn make_device_number(major: u16, minor: u32) -> Option<u32> {
+    ((minor as usize) < MINOR_COUNT).then_some(((major as u32) << MINOR_BITS) | minor)
+}
+
+pub(crate) fn split_device_number(device_number: u32) -> (u16, u32) {
+    (
+        (device_number >> MINOR_BITS) as u16,
+        device_number & ((1 << MINOR_BITS) - 1),
+    )
+}
@@ -0,0 +1,131 @@
+use crate::devices::virtual_fs::DeviceNode;
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Mutex};
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::{Error, ErrorExt, WasiFile};
+
+/// Source of the bytes read from a computer's `/dev/random` and `/dev/urandom`. Seeded randomly
+/// unless the host seeds it, in which case every read is reproducible.
+pub(crate) struct RandomSource {
+    rng: Mutex<StdRng>,
+}
+
+impl RandomSource {
+    pub(crate) fn reseed(&self, seed: u64) {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+    }
+
+    fn fill(&self, bufs: &mut [IoSliceMut<'_>]) -> usize {
+        let mut rng = self.rng.lock().unwrap();
+        for buf in bufs.iter_mut() {
+            rng.fill_bytes(buf);
+        }
+        bufs.iter().map(|buf| buf.len()).sum()
+    }
+}
+
+impl Default for RandomSource {
+    fn default() -> Self {
+        RandomSource {
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+}
+
+pub(super) enum PseudoDevice {
+    /// Reads nothing, and discards everything written.
+    Null,
+    /// Reads endless zeros, and discards everything written.
+    Zero,
+    /// Reads endless random bytes, and discards everything written. Serves both `/dev/random` and
+    /// `/dev/urandom`, as neither ever runs out.
+    Random(Arc<RandomSource>),
+}
+
+/// An fd open to one of the devices which are always present and always ready.
+pub(super) struct OpenPseudoDeviceFile {
+    pub(super) device: PseudoDevice,
+    pub(super) node: DeviceNode,
+    pub(super) read: bool,
+    pub(super) write: bool,
+}
+
+#[async_trait]
+impl WasiFile for OpenPseudoDeviceFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::APPEND)
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        if flags == FdFlags::APPEND {
+            Ok(())
+        } else {
+            Err(Error::not_supported()
+                .context("character devices do not support flags other than append"))
+        }
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(self.node.filestat(0))
+    }
+
+    async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        if !self.read {
+            return Err(Error::badf().context("file opened as writeonly"));
+        }
+
+        let n = match &self.device {
+            PseudoDevice::Null => 0,
+            PseudoDevice::Zero => bufs
+                .iter_mut()
+                .map(|buf| {
+                    buf.fill(0);
+                    buf.len()
+                })
+                .sum(),
+            PseudoDevice::Random(random) => random.fill(bufs),
+        };
+
+        Ok(n as u64)
+    }
+
+    async fn write_vectored<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        if !self.write {
+            return Err(Error::badf().context("file opened as readonly"));
+        }
+
+        Ok(bufs.iter().map(|buf| buf.len() as u64).sum())
+    }
+
+    fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(0)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        if self.read {
+            Ok(())
+        } else {
+            Err(Error::badf().context("file opened as writeonly"))
+        }
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        if self.write {
+            Ok(())
+        } else {
+            Err(Error::badf().context("file opened as readonly"))
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::devices::{Buffer, CharDevice, Plug, DEFAULT_LINK_CAPACITY};
+use async_trait::async_trait;
+use event_listener::EventListener;
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use wasi_common::file::{FdFlags, FileType, Filestat};
+use wasi_common::snapshots::preview_1::types::Errno;
+use wasi_common::{Error, ErrorExt, WasiFile};
+
+/// Input waiting to be read from a computer's stdin, pushed in by the host while the computer runs.
+pub struct Stdin {
+    buf: Mutex<Buffer>,
+    /// Unplugged once the host closes stdin, after which reads reach end of file.
+    plug: Arc<Plug>,
+}
+
+impl Stdin {
+    /// Pushes as much of the input as fits, returning how many bytes were pushed. Nothing is pushed
+    /// once stdin has been closed.
+    pub fn push(&self, input: &[u8]) -> usize {
+        if self.plug.is_unplugged() {
+            return 0;
+        }
+        self.buf
+            .lock()
+            .unwrap()
+            .write_vectored(&[IoSlice::new(input)])
+    }
+
+    /// Closes stdin, so that the guest reaches end of file once it has read everything pushed.
+    pub fn close(&self) {
+        self.plug.unplug();
+    }
+
+    pub(super) fn plug(&self) -> Arc<Plug> {
+        self.plug.clone()
+    }
+}
+
+impl Default for Stdin {
+    fn default() -> Self {
+        Stdin {
+            buf: Mutex::new(Buffer::new(DEFAULT_LINK_CAPACITY, None, None)),
+            plug: Arc::default(),
+        }
+    }
+}
+
+impl CharDevice for Stdin {
+    fn read_buf(&self) -> MutexGuard<'_, Buffer> {
+        self.buf.lock().unwrap()
+    }
+
+    fn write_vectored(&self, _bufs: &[IoSlice<'_>]) -> usize {
+        0
+    }
+
+    fn max_frame_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn is_ready_for_write(&self) -> bool {
+        false
+    }
+
+    fn listen_for_write(&self) -> Option<EventListener> {
+        None
+    }
+}
+
+/// The guest's fd 0. Unlike other devices, reading with nothing waiting fails with `EAGAIN` rather
+/// than returning nothing, as programs take an empty read from stdin to mean end of file.
+pub(crate) struct StdinFile {
+    stdin: Arc<Stdin>,
+    device_number: u32,
+}
+
+impl StdinFile {
+    pub(crate) fn new(stdin: Arc<Stdin>, device_number: u32) -> StdinFile {
+        StdinFile {
+            stdin,
+            device_number,
+        }
+    }
+}
+
+#[async_trait]
+impl WasiFile for StdinFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(Filestat {
+            device_id: self.device_number as u64,
+            inode: self.device_number as u64,
+            filetype: FileType::CharacterDevice,
+            nlink: 1,
+            size: 0,
+            atim: None,
+            mtim: None,
+            ctim: None,
+        })
+    }
+
+    async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        // Check for closing first, so that input pushed just before closing is not lost
+        let closed = self.stdin.plug.is_unplugged();
+        let n = self.stdin.read_buf().read_vectored(bufs);
+
+        if n == 0 && !closed && bufs.iter().any(|buf| !buf.is_empty()) {
+            return Err(Error::from(Errno::Again).context("no input is waiting"));
+        }
+
+        Ok(n as u64)
+    }
+
+    fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.stdin.read_buf().num_ready_bytes() as u64)
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Err(Error::badf().context("stdin is readonly"))
+    }
+}
@@ -0,0 +1,205 @@
+use crate::devices::{AttachedDuplexLink, CharDevice, LinkConfig};
+use host_api_sys::{FrameHeader, MacAddress};
+use std::collections::HashMap;
+use std::io::{IoSlice, IoSliceMut};
+use std::time::{Duration, Instant};
+
+/// Largest frame a switch port carries if the link config does not set one, matching an Ethernet
+/// frame without its checksum.
+pub const DEFAULT_ETHERNET_FRAME_SIZE: usize = 1514;
+
+/// Most addresses a switch remembers. Frames from new addresses are still forwarded once it is
+/// full, but their addresses are not learnt until old ones age out.
+pub const MAC_TABLE_CAPACITY: usize = 1024;
+/// How long a switch remembers an address after last seeing a frame from it, as on most real
+/// switches.
+pub const MAC_AGING_TIME: Duration = Duration::from_secs(300);
+
+/// The switch's ends of the links to each of its ports.
+struct Ports {
+    links: Vec<AttachedDuplexLink>,
+    max_frame_size: usize,
+    /// Port checked first for the next frame, so that busy ports cannot starve the ones after them.
+    next_port: usize,
+}
+
+impl Ports {
+    /// Creates the ports, returning them along with the other end of each port's link, to be
+    /// plugged into computers. Links are always framed.
+    ///
+    /// # Panics
+    /// Panics if there are no ports.
+    fn new(n_ports: usize, mut config: LinkConfig) -> (Ports, Vec<AttachedDuplexLink>) {
+        assert!(n_ports > 0, "a switch needs at least one port");
+
+        let max_frame_size = *config
+            .max_frame_size
+            .get_or_insert(DEFAULT_ETHERNET_FRAME_SIZE);
+
+        let (links, ends) = (0..n_ports)
+            .map(|_| AttachedDuplexLink::new_pair_with_config(config.clone()))
+            .unzip();
+
+        let ports = Ports {
+            links,
+            max_frame_size,
+            next_port: 0,
+        };
+
+        (ports, ends)
+    }
+
+    /// Buffer large enough for any frame the ports carry.
+    fn frame_buf(&self) -> Vec<u8> {
+        vec![0; self.max_frame_size]
+    }
+
+    /// Waits for the next frame to arrive on any port, reading it into the buffer and returning
+    /// the port and the frame's length. Ports take turns, starting after the last one read from.
+    async fn recv(&mut self, frame: &mut [u8]) -> (usize, usize) {
+        loop {
+            for offset in 0..self.links.len() {
+                let port = (self.next_port + offset) % self.links.len();
+                let n = self.links[port]
+                    .read_buf()
+                    .read_vectored(&mut [IoSliceMut::new(frame)]);
+
+                if n > 0 {
+                    self.next_port = port + 1;
+                    return (port, n);
+                }
+            }
+
+            let wait = self
+                .links
+                .iter()
+                .map(|link| link.read_buf().wait_until_ready_for_read());
+            futures::future::select_all(wait).await;
+        }
+    }
+
+    /// Sends the frame out of the port, dropping it if the port's link is full.
+    fn send(&self, port: usize, frame: &[u8]) {
+        self.links[port].write_vectored(&[IoSlice::new(frame)]);
+    }
+
+    fn flood(&self, ingress: usize, frame: &[u8]) {
+        for port in (0..self.links.len()).filter(|port| *port != ingress) {
+            self.send(port, frame);
+        }
+    }
+}
+
+/// A hub, which repeats every frame it receives out of every other port.
+pub struct Hub {
+    ports: Ports,
+}
+
+impl Hub {
+    /// Creates a hub, returning it along with the computer end of each port's link.
+    ///
+    /// # Panics
+    /// Panics if there are no ports.
+    pub fn new(n_ports: usize) -> (Hub, Vec<AttachedDuplexLink>) {
+        Self::with_config(n_ports, LinkConfig::default())
+    }
+
+    pub fn with_config(n_ports: usize, config: LinkConfig) -> (Hub, Vec<AttachedDuplexLink>) {
+        let (ports, ends) = Ports::new(n_ports, config);
+        (Hub { ports }, ends)
+    }
+
+    /// Forwards frames forever. Spawn this as a task, and abort the task to switch the hub off.
+    pub async fn run(mut self) {
+        let mut frame = self.ports.frame_buf();
+        loop {
+            let (ingress, len) = self.ports.recv(&mut frame).await;
+            self.ports.flood(ingress, &frame[..len]);
+        }
+    }
+}
+
+/// A learning switch. Frames must start with a [`FrameHeader`]. The switch learns which port each
+/// source address is behind, and sends frames for known destinations out of only that port.
+/// Frames for unknown, broadcast or multicast destinations are flooded like a [`Hub`]. At most
+/// [`MAC_TABLE_CAPACITY`] addresses are remembered, each for [`MAC_AGING_TIME`] after it was last
+/// seen.
+pub struct Switch {
+    ports: Ports,
+    /// The port each address is behind, and when a frame from it was last seen there.
+    mac_table: HashMap<MacAddress, (usize, Instant)>,
+}
+
+impl Switch {
+    /// Creates a switch, returning it along with the computer end of each port's link.
+    ///
+    /// # Panics
+    /// Panics if there are no ports.
+    pub fn new(n_ports: usize) -> (Switch, Vec<AttachedDuplexLink>) {
+        Self::with_config(n_ports, LinkConfig::default())
+    }
+
+    pub fn with_config(n_ports: usize, config: LinkConfig) -> (Switch, Vec<AttachedDuplexLink>) {
+        let (ports, ends) = Ports::new(n_ports, config);
+        let switch = Switch {
+            ports,
+            mac_table: HashMap::new(),
+        };
+
+        (switch, ends)
+    }
+
+    /// Forwards frames forever. Spawn this as a task, and abort the task to switch the switch off.
+    pub async fn run(mut self) {
+        let mut frame = self.ports.frame_buf();
+        loop {
+            let (ingress, len) = self.ports.recv(&mut frame).await;
+            self.forward(ingress, &frame[..len]);
+        }
+    }
+
+    fn forward(&mut self, ingress: usize, frame: &[u8]) {
+        let header = match frame.get(..std::mem::size_of::<FrameHeader>()) {
+            Some(header) => bytemuck::pod_read_unaligned::<FrameHeader>(header),
+            // Too short to have come from anywhere in particular
+            None => return self.ports.flood(ingress, frame),
+        };
+
+        let is_group = |mac: &MacAddress| mac[0] & 1 == 1;
+
+        if !is_group(&header.src) {
+            self.learn(header.src, ingress);
+        }
+
+        let now = Instant::now();
+        let egress = self
+            .mac_table
+            .get(&header.dst)
+            .filter(|(_port, seen)| now.duration_since(*seen) < MAC_AGING_TIME);
+
+        match egress {
+            Some(&(egress, _seen)) if !is_group(&header.dst) => {
+                // Destination is on the same segment it came from, so it has already seen it
+                if egress != ingress {
+                    self.ports.send(egress, frame);
+                }
+            }
+            _ => self.ports.flood(ingress, frame),
+        }
+    }
+
+    fn learn(&mut self, mac: MacAddress, port: usize) {
+        let now = Instant::now();
+
+        if self.mac_table.len() >= MAC_TABLE_CAPACITY && !self.mac_table.contains_key(&mac) {
+            self.mac_table
+                .retain(|_mac, (_port, seen)| now.duration_since(*seen) < MAC_AGING_TIME);
+
+            if self.mac_table.len() >= MAC_TABLE_CAPACITY {
+                return;
+            }
+        }
+
+        self.mac_table.insert(mac, (port, now));
+    }
+}
@@ -0,0 +1,691 @@
+use crate::devices::{Buffer, CharDevice};
+use event_listener::EventListener;
+use std::collections::VecDeque;
+use std::io::IoSlice;
+use std::sync::{Mutex, MutexGuard};
+
+/// Number of columns on a terminal's screen.
+pub const TTY_COLUMNS: usize = 80;
+/// Number of rows on a terminal's screen.
+pub const TTY_ROWS: usize = 25;
+/// Number of rows kept after scrolling off the top of the screen.
+pub const TTY_SCROLLBACK: usize = 1000;
+
+const TAB_WIDTH: usize = 8;
+/// Longest control sequence kept. Longer sequences are truncated, as no valid one is this long.
+const MAX_SEQUENCE_LEN: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    /// One of the 256 xterm colours, of which the first 16 are the standard and bright colours.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+    pub struct CellAttrs: u8 {
+        const BOLD = 1 << 0;
+        const DIM = 1 << 1;
+        const ITALIC = 1 << 2;
+        const UNDERLINE = 1 << 3;
+        const BLINK = 1 << 4;
+        const REVERSE = 1 << 5;
+        const HIDDEN = 1 << 6;
+        const STRIKETHROUGH = 1 << 7;
+    }
+}
+
+/// One character on the screen, along with how it is drawn.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: CellAttrs,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            ch: ' ',
+            fg: Color::Default,
+            bg: Color::Default,
+            attrs: CellAttrs::empty(),
+        }
+    }
+}
+
+/// A snapshot of a terminal's screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    /// Visible rows from top to bottom, each [`TTY_COLUMNS`] cells wide.
+    pub rows: Vec<Vec<Cell>>,
+    /// Rows which have scrolled off the top of the screen, oldest first.
+    pub scrollback: Vec<Vec<Cell>>,
+    /// Row and column of the cursor.
+    pub cursor: (usize, usize),
+    pub cursor_visible: bool,
+}
+
+impl Screen {
+    /// The visible rows as plain text, without trailing spaces.
+    pub fn text(&self) -> String {
+        self.rows
+            .iter()
+            .map(|row| {
+                let line: String = row.iter().map(|cell| cell.ch).collect();
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// A terminal attached to a computer, which draws everything written to `/dev/tty0` onto its
+/// screen. Understands the common VT100 and xterm control sequences for moving the cursor, erasing,
+/// scrolling and colours. As with a real tty, each line feed also returns the cursor to the first
+/// column.
+pub struct Tty {
+    terminal: Mutex<Terminal>,
+    /// Always empty, as the terminal has no keyboard of its own.
+    input: Mutex<Buffer>,
+}
+
+impl Tty {
+    pub fn screen(&self) -> Screen {
+        self.terminal.lock().unwrap().screen()
+    }
+}
+
+impl Default for Tty {
+    fn default() -> Self {
+        Tty {
+            terminal: Mutex::new(Terminal::new()),
+            input: Mutex::new(Buffer::new(0, None, None)),
+        }
+    }
+}
+
+impl CharDevice for Tty {
+    fn read_buf(&self) -> MutexGuard<'_, Buffer> {
+        self.input.lock().unwrap()
+    }
+
+    fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> usize {
+        let mut terminal = self.terminal.lock().unwrap();
+        for buf in bufs {
+            for &byte in buf.iter() {
+                terminal.advance(byte);
+            }
+        }
+        bufs.iter().map(|buf| buf.len()).sum()
+    }
+
+    fn max_frame_size(&self) -> Option<usize> {
+        None
+    }
+
+    fn is_ready_for_write(&self) -> bool {
+        true
+    }
+
+    fn listen_for_write(&self) -> Option<EventListener> {
+        None
+    }
+}
+
+enum State {
+    Ground,
+    Escape,
+    /// Waiting for the character set an `ESC (` or similar sequence designates, which is ignored.
+    Charset,
+    Csi(Vec<u8>),
+    /// Operating system commands, such as setting the window title, are ignored.
+    Osc,
+}
+
+struct Terminal {
+    rows: Vec<Vec<Cell>>,
+    scrollback: VecDeque<Vec<Cell>>,
+    row: usize,
+    col: usize,
+    /// Set after printing in the last column, so that the next character wraps onto a new line.
+    wrap_pending: bool,
+    /// Colours and attributes applied to printed characters.
+    pen: Cell,
+    saved: (usize, usize, Cell),
+    /// First and last rows scrolled by line feeds, inclusive.
+    scroll_top: usize,
+    scroll_bottom: usize,
+    cursor_visible: bool,
+    state: State,
+    /// Bytes of a UTF-8 character which has not been completely written yet.
+    utf8: Vec<u8>,
+}
+
+impl Terminal {
+    fn new() -> Terminal {
+        Terminal {
+            rows: vec![vec![Cell::default(); TTY_COLUMNS]; TTY_ROWS],
+            scrollback: VecDeque::new(),
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            pen: Cell::default(),
+            saved: (0, 0, Cell::default()),
+            scroll_top: 0,
+            scroll_bottom: TTY_ROWS - 1,
+            cursor_visible: true,
+            state: State::Ground,
+            utf8: Vec::new(),
+        }
+    }
+
+    fn screen(&self) -> Screen {
+        Screen {
+            rows: self.rows.clone(),
+            scrollback: self.scrollback.iter().cloned().collect(),
+            cursor: (self.row, self.col),
+            cursor_visible: self.cursor_visible,
+        }
+    }
+
+    fn advance(&mut self, byte: u8) {
+        match byte {
+            // Cancel any sequence in progress
+            0x18 | 0x1a => {
+                self.state = State::Ground;
+                return;
+            }
+            0x1b => {
+                self.utf8.clear();
+                self.state = State::Escape;
+                return;
+            }
+            _ => {}
+        }
+
+        match std::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => match byte {
+                0x00..=0x1f | 0x7f => self.execute(byte),
+                _ => self.decode(byte),
+            },
+            State::Escape => self.escape(byte),
+            State::Charset => {}
+            State::Csi(mut seq) => match byte {
+                0x40..=0x7e => self.csi(&seq, byte),
+                0x00..=0x1f => {
+                    self.execute(byte);
+                    self.state = State::Csi(seq);
+                }
+                _ => {
+                    if seq.len() < MAX_SEQUENCE_LEN {
+                        seq.push(byte);
+                    }
+                    self.state = State::Csi(seq);
+                }
+            },
+            State::Osc => {
+                if byte != 0x07 {
+                    self.state = State::Osc;
+                }
+            }
+        }
+    }
+
+    /// Collects the bytes of UTF-8 characters, printing each once complete.
+    fn decode(&mut self, byte: u8) {
+        // A character cut short by the start of another
+        if !self.utf8.is_empty() && byte & 0xc0 != 0x80 {
+            self.utf8.clear();
+            self.print(char::REPLACEMENT_CHARACTER);
+        }
+
+        self.utf8.push(byte);
+
+        let len = match self.utf8[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+
+        if self.utf8.len() < len {
+            return;
+        }
+
+        let ch = std::str::from_utf8(&self.utf8)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.utf8.clear();
+        self.print(ch);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // Backspace
+            0x08 => {
+                self.col = self.col.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            // Tab
+            0x09 => {
+                self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(TTY_COLUMNS - 1);
+                self.wrap_pending = false;
+            }
+            // Line feed, vertical tab and form feed
+            0x0a..=0x0c => {
+                self.col = 0;
+                self.line_feed();
+            }
+            // Carriage return
+            0x0d => {
+                self.col = 0;
+                self.wrap_pending = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn escape(&mut self, byte: u8) {
+        match byte {
+            b'[' => self.state = State::Csi(Vec::new()),
+            b']' => self.state = State::Osc,
+            b'(' | b')' | b'*' | b'+' => self.state = State::Charset,
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.line_feed(),
+            b'E' => {
+                self.col = 0;
+                self.line_feed();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => *self = Terminal::new(),
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, seq: &[u8], action: u8) {
+        let (private, seq) = match seq.first() {
+            Some(b'?' | b'>' | b'<' | b'=') => (true, &seq[1..]),
+            _ => (false, seq),
+        };
+
+        // Sequences with intermediate bytes are not supported
+        if seq
+            .iter()
+            .any(|&b| !(b.is_ascii_digit() || b == b';' || b == b':'))
+        {
+            return;
+        }
+
+        let params: Vec<u16> = std::str::from_utf8(seq)
+            .unwrap()
+            .split([';', ':'])
+            .map(|param| param.parse().unwrap_or(0))
+            .collect();
+        let param = |i: usize| params.get(i).copied().unwrap_or(0) as usize;
+        // Counts of zero mean one
+        let count = param(0).max(1);
+
+        if private {
+            if let (b'h' | b'l', 25) = (action, param(0)) {
+                self.cursor_visible = action == b'h';
+            }
+            return;
+        }
+
+        match action {
+            b'A' => self.move_cursor(self.row.saturating_sub(count), self.col),
+            b'B' | b'e' => self.move_cursor(self.row + count, self.col),
+            b'C' | b'a' => self.move_cursor(self.row, self.col + count),
+            b'D' => self.move_cursor(self.row, self.col.saturating_sub(count)),
+            b'E' => self.move_cursor(self.row + count, 0),
+            b'F' => self.move_cursor(self.row.saturating_sub(count), 0),
+            b'G' | b'`' => self.move_cursor(self.row, count - 1),
+            b'H' | b'f' => self.move_cursor(param(0).max(1) - 1, param(1).max(1) - 1),
+            b'd' => self.move_cursor(count - 1, self.col),
+            b'J' => match param(0) {
+                0 => {
+                    self.erase_line(self.col, TTY_COLUMNS);
+                    self.erase_rows(self.row + 1, TTY_ROWS);
+                }
+                1 => {
+                    self.erase_rows(0, self.row);
+                    self.erase_line(0, self.col + 1);
+                }
+                2 => self.erase_rows(0, TTY_ROWS),
+                3 => self.scrollback.clear(),
+                _ => {}
+            },
+            b'K' => match param(0) {
+                0 => self.erase_line(self.col, TTY_COLUMNS),
+                1 => self.erase_line(0, self.col + 1),
+                2 => self.erase_line(0, TTY_COLUMNS),
+                _ => {}
+            },
+            b'L' if (self.scroll_top..=self.scroll_bottom).contains(&self.row) => {
+                for _ in 0..count.min(self.scroll_bottom - self.row + 1) {
+                    self.rows.remove(self.scroll_bottom);
+                    self.rows.insert(self.row, self.blank_row());
+                }
+            }
+            b'M' if (self.scroll_top..=self.scroll_bottom).contains(&self.row) => {
+                for _ in 0..count.min(self.scroll_bottom - self.row + 1) {
+                    self.rows.remove(self.row);
+                    self.rows.insert(self.scroll_bottom, self.blank_row());
+                }
+            }
+            b'@' => {
+                let blank = self.blank();
+                let row = &mut self.rows[self.row];
+                for _ in 0..count.min(TTY_COLUMNS - self.col) {
+                    row.insert(self.col, blank);
+                }
+                row.truncate(TTY_COLUMNS);
+            }
+            b'P' => {
+                let blank = self.blank();
+                let row = &mut self.rows[self.row];
+                for _ in 0..count.min(TTY_COLUMNS - self.col) {
+                    row.remove(self.col);
+                    row.push(blank);
+                }
+            }
+            b'X' => self.erase_line(self.col, (self.col + count).min(TTY_COLUMNS)),
+            b'S' => self.scroll_up(count),
+            b'T' => self.scroll_down(count),
+            b'm' => self.select_graphic_rendition(&params),
+            b'r' => {
+                let top = param(0).max(1) - 1;
+                let bottom = if param(1) == 0 { TTY_ROWS } else { param(1) }.min(TTY_ROWS) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_cursor(0, 0);
+                }
+            }
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+
+        while let Some(param) = params.next() {
+            match param {
+                0 => self.pen = Cell::default(),
+                1 => self.pen.attrs.insert(CellAttrs::BOLD),
+                2 => self.pen.attrs.insert(CellAttrs::DIM),
+                3 => self.pen.attrs.insert(CellAttrs::ITALIC),
+                4 => self.pen.attrs.insert(CellAttrs::UNDERLINE),
+                5 => self.pen.attrs.insert(CellAttrs::BLINK),
+                7 => self.pen.attrs.insert(CellAttrs::REVERSE),
+                8 => self.pen.attrs.insert(CellAttrs::HIDDEN),
+                9 => self.pen.attrs.insert(CellAttrs::STRIKETHROUGH),
+                22 => self.pen.attrs.remove(CellAttrs::BOLD | CellAttrs::DIM),
+                23 => self.pen.attrs.remove(CellAttrs::ITALIC),
+                24 => self.pen.attrs.remove(CellAttrs::UNDERLINE),
+                25 => self.pen.attrs.remove(CellAttrs::BLINK),
+                27 => self.pen.attrs.remove(CellAttrs::REVERSE),
+                28 => self.pen.attrs.remove(CellAttrs::HIDDEN),
+                29 => self.pen.attrs.remove(CellAttrs::STRIKETHROUGH),
+                30..=37 => self.pen.fg = Color::Indexed((param - 30) as u8),
+                38 => self.pen.fg = extended_color(&mut params),
+                39 => self.pen.fg = Color::Default,
+                40..=47 => self.pen.bg = Color::Indexed((param - 40) as u8),
+                48 => self.pen.bg = extended_color(&mut params),
+                49 => self.pen.bg = Color::Default,
+                90..=97 => self.pen.fg = Color::Indexed((param - 90 + 8) as u8),
+                100..=107 => self.pen.bg = Color::Indexed((param - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+
+    fn print(&mut self, ch: char) {
+        if self.wrap_pending {
+            self.col = 0;
+            self.line_feed();
+        }
+
+        self.rows[self.row][self.col] = Cell { ch, ..self.pen };
+
+        if self.col + 1 == TTY_COLUMNS {
+            self.wrap_pending = true;
+        } else {
+            self.col += 1;
+        }
+    }
+
+    fn move_cursor(&mut self, row: usize, col: usize) {
+        self.row = row.min(TTY_ROWS - 1);
+        self.col = col.min(TTY_COLUMNS - 1);
+        self.wrap_pending = false;
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = (self.row, self.col, self.pen);
+    }
+
+    fn restore_cursor(&mut self) {
+        let (row, col, pen) = self.saved;
+        self.move_cursor(row, col);
+        self.pen = pen;
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+
+        if self.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < TTY_ROWS {
+            self.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+
+        if self.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.row > 0 {
+            self.row -= 1;
+        }
+    }
+
+    /// Scrolls the scrolling region up, keeping rows scrolled off the top of the screen.
+    fn scroll_up(&mut self, count: usize) {
+        for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+            let row = self.rows.remove(self.scroll_top);
+            self.rows.insert(self.scroll_bottom, self.blank_row());
+
+            if self.scroll_top == 0 {
+                if self.scrollback.len() == TTY_SCROLLBACK {
+                    self.scrollback.pop_front();
+                }
+                self.scrollback.push_back(row);
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        for _ in 0..count.min(self.scroll_bottom - self.scroll_top + 1) {
+            self.rows.remove(self.scroll_bottom);
+            self.rows.insert(self.scroll_top, self.blank_row());
+        }
+    }
+
+    /// Erases the columns in the given range of the cursor's row.
+    fn erase_line(&mut self, start: usize, end: usize) {
+        let blank = self.blank();
+        self.rows[self.row][start..end].fill(blank);
+    }
+
+    fn erase_rows(&mut self, start: usize, end: usize) {
+        for row in start..end {
+            self.rows[row] = self.blank_row();
+        }
+    }
+
+    /// Erased cells keep the current background colour.
+    fn blank(&self) -> Cell {
+        Cell {
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
+    }
+
+    fn blank_row(&self) -> Vec<Cell> {
+        vec![self.blank(); TTY_COLUMNS]
+    }
+}
+
+/// Reads the colour of an `SGR 38` or `SGR 48` sequence, given as either `5;n` or `2;r;g;b`.
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Color {
+    match params.next() {
+        Some(5) => Color::Indexed(params.next().unwrap_or(0) as u8),
+        Some(2) => {
+            let mut component = || params.next().unwrap_or(0) as u8;
+            Color::Rgb(component(), component(), component())
+        }
+        _ => Color::Default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(tty: &Tty, bytes: &[u8]) {
+        tty.write_vectored(&[IoSlice::new(bytes)]);
+    }
+
+    fn row_text(row: &[Cell]) -> String {
+        let line: String = row.iter().map(|cell| cell.ch).collect();
+        line.trim_end().to_string()
+    }
+
+    #[test]
+    fn moves_cursor() {
+        let tty = Tty::default();
+
+        write(&tty, b"\x1b[5;10H");
+        assert_eq!(tty.screen().cursor, (4, 9));
+
+        write(&tty, b"\x1b[2A\x1b[3C");
+        assert_eq!(tty.screen().cursor, (2, 12));
+
+        write(&tty, b"\x1b[B\x1b[D");
+        assert_eq!(tty.screen().cursor, (3, 11));
+
+        // Clamped to the screen
+        write(&tty, b"\x1b[99;999H");
+        assert_eq!(tty.screen().cursor, (TTY_ROWS - 1, TTY_COLUMNS - 1));
+
+        write(&tty, b"\x1b[H");
+        assert_eq!(tty.screen().cursor, (0, 0));
+    }
+
+    #[test]
+    fn parses_csi_params() {
+        let tty = Tty::default();
+
+        // Missing and zero parameters default to one
+        write(&tty, b"\x1b[;5H");
+        assert_eq!(tty.screen().cursor, (0, 4));
+        write(&tty, b"\x1b[0B");
+        assert_eq!(tty.screen().cursor, (1, 4));
+
+        write(&tty, b"\x1b[1;38;5;196;48;2;1;2;3mx");
+        let cell = tty.screen().rows[1][4];
+        assert_eq!(cell.ch, 'x');
+        assert_eq!(cell.attrs, CellAttrs::BOLD);
+        assert_eq!(cell.fg, Color::Indexed(196));
+        assert_eq!(cell.bg, Color::Rgb(1, 2, 3));
+
+        write(&tty, b"\x1b[?25l");
+        assert!(!tty.screen().cursor_visible);
+
+        // Sequences with intermediate bytes are ignored
+        write(&tty, b"\x1b[2 q");
+        assert_eq!(tty.screen().cursor, (1, 5));
+    }
+
+    #[test]
+    fn wraps_at_last_column() {
+        let tty = Tty::default();
+
+        write(&tty, &[b'a'; TTY_COLUMNS]);
+        // The cursor stays in the last column until the next character is printed
+        assert_eq!(tty.screen().cursor, (0, TTY_COLUMNS - 1));
+
+        write(&tty, b"b");
+        let screen = tty.screen();
+        assert_eq!(screen.cursor, (1, 1));
+        assert_eq!(row_text(&screen.rows[0]), "a".repeat(TTY_COLUMNS));
+        assert_eq!(row_text(&screen.rows[1]), "b");
+
+        // A line feed after a full line does not leave a blank line
+        write(&tty, b"\r\x1b[2B");
+        write(&tty, &[b'c'; TTY_COLUMNS]);
+        write(&tty, b"\n");
+        assert_eq!(tty.screen().cursor, (4, 0));
+    }
+
+    #[test]
+    fn scrolls_into_scrollback() {
+        let tty = Tty::default();
+
+        for line in 0..TTY_ROWS + 2 {
+            write(&tty, format!("{line}\n").as_bytes());
+        }
+
+        let screen = tty.screen();
+        assert_eq!(screen.cursor, (TTY_ROWS - 1, 0));
+        assert_eq!(screen.scrollback.len(), 3);
+        assert_eq!(row_text(&screen.scrollback[0]), "0");
+        assert_eq!(row_text(&screen.scrollback[2]), "2");
+        assert_eq!(row_text(&screen.rows[0]), "3");
+
+        for _ in 0..TTY_SCROLLBACK {
+            write(&tty, b"\n");
+        }
+        assert_eq!(tty.screen().scrollback.len(), TTY_SCROLLBACK);
+
+        write(&tty, b"\x1b[3J");
+        assert!(tty.screen().scrollback.is_empty());
+    }
+
+    #[test]
+    fn scrolls_region() {
+        let tty = Tty::default();
+
+        for line in 0..TTY_ROWS {
+            write(&tty, format!("\x1b[{};1H{line}", line + 1).as_bytes());
+        }
+
+        // Rows scrolled out of a region which does not start at the top are discarded
+        write(&tty, b"\x1b[2;4r\x1b[4;1H\n");
+        let screen = tty.screen();
+        assert!(screen.scrollback.is_empty());
+        assert_eq!(
+            screen.rows[..5]
+                .iter()
+                .map(|row| row_text(row))
+                .collect::<Vec<_>>(),
+            ["0", "2", "3", "", "4"]
+        );
+
+        write(&tty, b"\x1b[2;1H\x1bM");
+        assert_eq!(row_text(&tty.screen().rows[1]), "");
+        assert_eq!(row_text(&tty.screen().rows[2]), "2");
+    }
+}
@@ -1,48 +1,494 @@
-use crate::devices::{AttachedDuplexLink, DeviceType};
+use crate::devices::framebuffer::{Framebuffer, OpenFramebufferFile};
+use crate::devices::numbers::split_device_number;
+use crate::devices::pseudo::{OpenPseudoDeviceFile, PseudoDevice};
+use crate::devices::{CharDevice, Device, DeviceType, Devices, Plug, INPUT_KEYBOARD, INPUT_MOUSE};
 use crate::Computer;
 use async_trait::async_trait;
 use std::any::Any;
-use std::io::Write;
-use std::io::{IoSlice, IoSliceMut, Read};
+use std::collections::VecDeque;
+use std::io::{IoSlice, IoSliceMut};
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
 use wasi_common::file::{FdFlags, FileType, Filestat, OFlags};
+use wasi_common::snapshots::preview_1::types::Errno;
 use wasi_common::{Error, ErrorExt};
 use wasi_common::{SystemTimeSpec, WasiDir, WasiFile};
 
-const DEV_MAJOR: u16 = 511;
-const ETHERNET_MAJOR: u16 = 510;
-const WIRELESS_MAJOR: u16 = 509;
+/// Minors match those of the Linux memory devices.
+const NULL_MINOR: u32 = 3;
+const ZERO_MINOR: u32 = 5;
+const RANDOM_MINOR: u32 = 8;
+const URANDOM_MINOR: u32 = 9;
 
-fn make_device_number(major: u16, minor: u32) -> u32 {
-    ((major as u32) << 20) | minor
+/// A device's file in `/dev`, as reported in the filestat of every fd open to it.
+#[derive(Copy, Clone)]
+pub(super) struct DeviceNode {
+    device_number: u32,
+    /// Stays the same across opens, even for devices like `/dev/events` whose device number
+    /// differs for each fd.
+    inode: u64,
+    /// When `/dev` was mounted, which is given as all of the file's timestamps.
+    mounted: SystemTime,
+}
+
+impl DeviceNode {
+    fn new(device_number: u32, mounted: SystemTime) -> DeviceNode {
+        DeviceNode {
+            device_number,
+            inode: device_number as u64,
+            mounted,
+        }
+    }
+
+    pub(super) fn filestat(&self, size: u64) -> Filestat {
+        Filestat {
+            device_id: self.device_number as u64,
+            inode: self.inode,
+            filetype: FileType::CharacterDevice,
+            nlink: 1,
+            size,
+            atim: Some(self.mounted),
+            mtim: Some(self.mounted),
+            ctim: Some(self.mounted),
+        }
+    }
 }
 
-pub fn decompose_device(device_no: u64) -> Option<(DeviceType, usize)> {
-    let major = ((device_no >> 20) & ((1 << 12) - 1)) as u16;
-    let minor = (device_no & ((1 << 20) - 1)) as u32;
+/// Directories of `/dev` by their path from it, with `/dev` itself first and every other directory
+/// after its parent. A directory's inode is the device number of `/dev`, with its position here as
+/// minor.
+const DIRS: &[&str] = &["", "net", "input", "char", "disk", "disk/by-id"];
 
-    let dev_type = match major {
-        ETHERNET_MAJOR => DeviceType::Ethernet,
-        WIRELESS_MAJOR => DeviceType::Wireless,
-        _ => return None,
-    };
+/// Most symlinks followed while resolving a path, as on Linux.
+const MAX_SYMLINKS: usize = 40;
 
-    Some((dev_type, minor as usize))
+/// An entry in one of the directories of `/dev`.
+struct DirEntry {
+    name: String,
+    inode: u64,
+    kind: EntryKind,
 }
 
+enum EntryKind {
+    /// A directory, by its position in [`DIRS`].
+    Dir(usize),
+    /// A device, by its path from `/dev`.
+    Device(String),
+    /// An alias for another entry, by its path from the alias's directory.
+    Symlink(String),
+}
+
+/// A computer's `/dev`, or one of its subdirectories. Each device's inode is its device number, so
+/// inodes are unique and stay the same for as long as the device is plugged in. Devices are also
+/// grouped into subdirectories:
+///
+/// - `net/`, holding symlinks to the network devices.
+/// - `input/`, holding the keyboard and mouse.
+/// - `char/`, holding symlinks to every device named by its device number, as `major:minor`.
+/// - `disk/by-id/`, where disks would be found by their ids. It is empty, as there are no block
+///   devices.
+#[derive(Clone)]
 pub struct DevicesDir {
     computer: Arc<RwLock<Computer>>,
+    /// Inode of the directory `/dev` is mounted in, as the guest sees it.
+    parent_inode: u64,
+    mounted: SystemTime,
+    /// Position of the directory in [`DIRS`].
+    dir: usize,
 }
 
 impl DevicesDir {
-    pub fn new(computer: Arc<RwLock<Computer>>) -> DevicesDir {
-        DevicesDir { computer }
+    pub fn new(computer: Arc<RwLock<Computer>>, parent_inode: u64) -> DevicesDir {
+        DevicesDir {
+            computer,
+            parent_inode,
+            mounted: SystemTime::now(),
+            dir: 0,
+        }
+    }
+
+    fn node(&self, devs: &Devices, class: &str, minor: u32) -> DeviceNode {
+        DeviceNode::new(devs.numbers.device_number(class, minor), self.mounted)
+    }
+
+    fn dir_inode(devs: &Devices, dir: usize) -> u64 {
+        devs.numbers.device_number("dev", dir as u32) as u64
+    }
+
+    fn dir_filestat(&self, devs: &Devices, dir: usize) -> Filestat {
+        Filestat {
+            device_id: devs.numbers.device_number("dev", 0) as u64,
+            inode: DevicesDir::dir_inode(devs, dir),
+            filetype: FileType::Directory,
+            // From its own `.`, its entry in its parent, and the `..` of each subdirectory
+            nlink: 2 + child_dirs(dir).count() as u64,
+            size: 0,
+            atim: Some(self.mounted),
+            mtim: Some(self.mounted),
+            ctim: Some(self.mounted),
+        }
+    }
+
+    fn symlink_filestat(&self, devs: &Devices, inode: u64, target: &str) -> Filestat {
+        Filestat {
+            device_id: devs.numbers.device_number("dev", 0) as u64,
+            inode,
+            filetype: FileType::SymbolicLink,
+            nlink: 1,
+            size: target.len() as u64,
+            atim: Some(self.mounted),
+            mtim: Some(self.mounted),
+            ctim: Some(self.mounted),
+        }
+    }
+
+    /// Every device file, by its path from `/dev`, along with the device number it is listed
+    /// with.
+    fn device_files(devs: &Devices) -> Vec<(String, u32)> {
+        let mut files = vec![
+            (
+                String::from("events"),
+                devs.numbers.device_number("events", 0),
+            ),
+            (String::from("fb0"), devs.numbers.device_number("fb", 0)),
+            (String::from("tty0"), devs.numbers.device_number("tty", 0)),
+            (
+                String::from("null"),
+                devs.numbers.device_number("mem", NULL_MINOR),
+            ),
+            (
+                String::from("zero"),
+                devs.numbers.device_number("mem", ZERO_MINOR),
+            ),
+            (
+                String::from("random"),
+                devs.numbers.device_number("mem", RANDOM_MINOR),
+            ),
+            (
+                String::from("urandom"),
+                devs.numbers.device_number("mem", URANDOM_MINOR),
+            ),
+            (
+                String::from("input/keyboard"),
+                devs.numbers.device_number("input", INPUT_KEYBOARD as u32),
+            ),
+            (
+                String::from("input/mouse"),
+                devs.numbers.device_number("input", INPUT_MOUSE as u32),
+            ),
+        ];
+
+        for idx in devs.indices(DeviceType::Ethernet) {
+            let number = devs.numbers.device_number("ethernet", idx as u32);
+            files.push((format!("ethernet{idx}"), number));
+        }
+
+        for idx in devs.indices(DeviceType::Wireless) {
+            let number = devs.numbers.device_number("wireless", idx as u32);
+            files.push((format!("wireless{idx}"), number));
+        }
+
+        for (idx, custom) in &devs.custom_devices {
+            let number = devs.numbers.device_number("custom", *idx as u32);
+            files.push((custom.device.name().to_owned(), number));
+        }
+
+        files
+    }
+
+    /// The entries of a directory, other than `.` and `..`.
+    fn entries(devs: &Devices, dir: usize) -> Vec<DirEntry> {
+        let files = DevicesDir::device_files(devs);
+
+        // Symlinks have the position of their directory in the upper bits of their inode, so that
+        // they never collide with devices or directories
+        let to_dev = "../".repeat(DIRS[dir].split('/').count());
+        let symlink = |name: String, number: u32, path: &str| DirEntry {
+            name,
+            inode: ((dir as u64) << 32) | number as u64,
+            kind: EntryKind::Symlink(format!("{to_dev}{path}")),
+        };
+
+        let dirs = child_dirs(dir).map(|child| DirEntry {
+            name: DIRS[child].rsplit('/').next().unwrap().to_owned(),
+            inode: DevicesDir::dir_inode(devs, child),
+            kind: EntryKind::Dir(child),
+        });
+
+        let others: Vec<DirEntry> = match DIRS[dir] {
+            "" => files
+                .into_iter()
+                .filter(|(path, _number)| !path.contains('/'))
+                .map(|(path, number)| DirEntry {
+                    name: path.clone(),
+                    inode: number as u64,
+                    kind: EntryKind::Device(path),
+                })
+                .collect(),
+            "input" => files
+                .into_iter()
+                .filter_map(|(path, number)| {
+                    let name = path.strip_prefix("input/")?.to_owned();
+                    Some(DirEntry {
+                        name,
+                        inode: number as u64,
+                        kind: EntryKind::Device(path),
+                    })
+                })
+                .collect(),
+            "net" => files
+                .iter()
+                .filter(|(path, _number)| {
+                    matches!(parse_dev(path), ("ethernet" | "wireless", Some(_)))
+                })
+                .map(|(path, number)| symlink(path.clone(), *number, path))
+                .collect(),
+            "char" => files
+                .iter()
+                // Each fd open to `/dev/events` has its own device number
+                .filter(|(path, _number)| path != "events")
+                .map(|(path, number)| {
+                    let (major, minor) = split_device_number(*number);
+                    symlink(format!("{major}:{minor}"), *number, path)
+                })
+                .collect(),
+            "disk" | "disk/by-id" => Vec::new(),
+            _ => unreachable!("every directory is listed"),
+        };
+
+        dirs.chain(others).collect()
+    }
+
+    /// Finds what a path from this directory leads to, along with its inode.
+    fn resolve(
+        &self,
+        devs: &Devices,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<(EntryKind, u64), Error> {
+        let mut dir = self.dir;
+        let mut components: VecDeque<String> = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(String::from)
+            .collect();
+        let mut symlinks_followed = 0;
+
+        while let Some(component) = components.pop_front() {
+            match component.as_str() {
+                "." => {}
+                ".." if dir == 0 => {
+                    return Err(Error::perm().context("path leads out of /dev/"));
+                }
+                ".." => dir = parent_dir(dir),
+                name => {
+                    let entry = DevicesDir::entries(devs, dir)
+                        .into_iter()
+                        .find(|entry| entry.name == name)
+                        .ok_or_else(Error::not_found)?;
+
+                    match entry.kind {
+                        EntryKind::Dir(child) => dir = child,
+                        EntryKind::Symlink(target) if follow_symlinks || !components.is_empty() => {
+                            symlinks_followed += 1;
+                            if symlinks_followed > MAX_SYMLINKS {
+                                return Err(Error::from(Errno::Loop).context("too many symlinks"));
+                            }
+
+                            for component in target.split('/').rev() {
+                                components.push_front(component.to_owned());
+                            }
+                        }
+                        kind if components.is_empty() => return Ok((kind, entry.inode)),
+                        _ => return Err(Error::not_dir()),
+                    }
+                }
+            }
+        }
+
+        Ok((EntryKind::Dir(dir), DevicesDir::dir_inode(devs, dir)))
+    }
+
+    /// The filestat of the device at the given path from `/dev`, found without opening it so that
+    /// it does not matter whether the device can be read or written, and so that no event queue
+    /// is subscribed to and no device plugged in by the host is opened.
+    fn device_filestat(&self, devs: &Devices, path: &str, inode: u64) -> Filestat {
+        // Every device's inode is the device number it is listed with, which for `/dev/events` is
+        // that of its first queue rather than of any fd's own
+        let node = DeviceNode::new(inode as u32, self.mounted);
+
+        match parse_dev(path) {
+            ("fb", Some(0)) => node.filestat(Framebuffer::len()),
+            _ => {
+                let mut filestat = node.filestat(0);
+                if let Some((_idx, device, _plug)) = devs.custom_device(path) {
+                    device.filestat(&mut filestat);
+                }
+                filestat
+            }
+        }
+    }
+
+    /// Opens the device at the given path from `/dev`.
+    fn open_device(
+        &self,
+        devs: &mut Devices,
+        path: &str,
+        read: bool,
+        write: bool,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let (name, idx) = parse_dev(path);
+
+        match (name, idx) {
+            ("ethernet" | "wireless", Some(idx)) => {
+                let dev_type = if name == "ethernet" {
+                    DeviceType::Ethernet
+                } else {
+                    DeviceType::Wireless
+                };
+
+                let (device, plug) = devs.device(dev_type, idx).ok_or_else(Error::not_found)?;
+
+                let open_file = OpenCharDeviceFile {
+                    device,
+                    plug,
+                    node: self.node(devs, name, idx as u32),
+                    read,
+                    write,
+                };
+
+                Ok(Box::new(open_file))
+            }
+            ("events", None) => {
+                if write {
+                    return Err(Error::perm().context("/dev/events is readonly"));
+                }
+
+                let (idx, queue) = devs.subscribe_events().ok_or_else(|| {
+                    Error::from(Errno::Nfile).context("too many /dev/events queues are open")
+                })?;
+
+                let open_file = OpenCharDeviceFile {
+                    device: queue,
+                    plug: Arc::new(Plug::default()),
+                    node: DeviceNode {
+                        // Each fd has its own queue, but they are all the same file
+                        inode: devs.numbers.device_number("events", 0) as u64,
+                        ..self.node(devs, "events", idx as u32)
+                    },
+                    read,
+                    write,
+                };
+
+                Ok(Box::new(open_file))
+            }
+            ("tty", Some(0)) => {
+                if read {
+                    return Err(Error::perm().context("/dev/tty0 is writeonly"));
+                }
+
+                let (device, plug) = devs.device(DeviceType::Tty, 0).unwrap();
+
+                let open_file = OpenCharDeviceFile {
+                    device,
+                    plug,
+                    node: self.node(devs, "tty", 0),
+                    read,
+                    write,
+                };
+
+                Ok(Box::new(open_file))
+            }
+            ("input/keyboard" | "input/mouse", None) => {
+                if write {
+                    return Err(Error::perm().context("input devices are readonly"));
+                }
+
+                let idx = if name == "input/keyboard" {
+                    INPUT_KEYBOARD
+                } else {
+                    INPUT_MOUSE
+                };
+                let (device, plug) = devs.device(DeviceType::Input, idx).unwrap();
+
+                let open_file = OpenCharDeviceFile {
+                    device,
+                    plug,
+                    node: self.node(devs, "input", idx as u32),
+                    read,
+                    write,
+                };
+
+                Ok(Box::new(open_file))
+            }
+            ("null" | "zero" | "random" | "urandom", None) => {
+                let (device, minor) = match name {
+                    "null" => (PseudoDevice::Null, NULL_MINOR),
+                    "zero" => (PseudoDevice::Zero, ZERO_MINOR),
+                    "random" => (PseudoDevice::Random(devs.random.clone()), RANDOM_MINOR),
+                    _ => (PseudoDevice::Random(devs.random.clone()), URANDOM_MINOR),
+                };
+
+                let open_file = OpenPseudoDeviceFile {
+                    device,
+                    node: self.node(devs, "mem", minor),
+                    read,
+                    write,
+                };
+
+                Ok(Box::new(open_file))
+            }
+            ("fb", Some(0)) => Ok(Box::new(OpenFramebufferFile::new(
+                devs.framebuffer(),
+                self.node(devs, "fb", 0),
+                read,
+                write,
+            ))),
+            _ => {
+                let (idx, device, plug) = devs.custom_device(path).ok_or_else(Error::not_found)?;
+                device.open(read, write)?;
+
+                Ok(Box::new(OpenDeviceFile {
+                    device,
+                    plug,
+                    node: self.node(devs, "custom", idx as u32),
+                    read,
+                    write,
+                }))
+            }
+        }
     }
 }
 
-fn parse_dev(name: &str) -> (&str, Option<u8>) {
+/// Position in [`DIRS`] of a directory's parent. `/dev` is given as its own parent, though its `..`
+/// leads out of it.
+fn parent_dir(dir: usize) -> usize {
+    let parent = DIRS[dir]
+        .rsplit_once('/')
+        .map_or("", |(parent, _name)| parent);
+    DIRS.iter().position(|path| *path == parent).unwrap()
+}
+
+/// Positions in [`DIRS`] of the directories directly inside a directory.
+fn child_dirs(dir: usize) -> impl Iterator<Item = usize> {
+    (1..DIRS.len()).filter(move |&child| parent_dir(child) == dir)
+}
+
+/// Names of the devices and directories which are built in, without their index.
+const BUILT_IN_NAMES: &[&str] = &[
+    "ethernet", "wireless", "events", "input", "null", "zero", "random", "urandom", "tty", "fb",
+    "net", "char", "disk",
+];
+
+/// Whether the name is, or could later be, taken by a built-in device or directory.
+pub(super) fn is_reserved_name(name: &str) -> bool {
+    let (name, _idx) = parse_dev(name);
+    BUILT_IN_NAMES.contains(&name)
+}
+
+fn parse_dev(name: &str) -> (&str, Option<usize>) {
     let digit = name.chars().position(|c| c.is_ascii_digit());
     (
         &name[..digit.unwrap_or(name.len())],
@@ -58,14 +504,14 @@ impl WasiDir for DevicesDir {
 
     async fn open_file(
         &self,
-        _symlink_follow: bool,
+        symlink_follow: bool,
         path: &str,
         flags: OFlags,
         read: bool,
         write: bool,
         fdflags: FdFlags,
     ) -> Result<Box<dyn WasiFile>, Error> {
-        let devs = &self.computer.read().unwrap().devices;
+        let devs = &mut self.computer.write().unwrap().devices;
 
         if flags.intersects(OFlags::all()) {
             return Err(Error::not_supported().context("device supports no opening flags"));
@@ -75,36 +521,27 @@ impl WasiDir for DevicesDir {
             return Err(Error::not_supported().context("SYNC family flags unsupported"));
         }
 
-        let (name, idx) = parse_dev(path);
-
-        match (name, idx) {
-            ("ethernet" | "wireless", Some(idx)) => {
-                let (dev_major, net) = if name == "ethernet" {
-                    (ETHERNET_MAJOR, devs.ethernet_links.get(idx as usize))
-                } else {
-                    (WIRELESS_MAJOR, devs.wireless_links.get(idx as usize))
-                };
-
-                let open_file = OpenDuplexLinkFile {
-                    link: net.ok_or_else(Error::not_found)?.clone(),
-                    device_number: make_device_number(dev_major, idx as u32),
-                    read,
-                    write,
-                };
-
-                Ok(Box::new(open_file))
+        match self.resolve(devs, path, symlink_follow)? {
+            (EntryKind::Device(path), _inode) => self.open_device(devs, &path, read, write),
+            (EntryKind::Dir(dir), _inode) => Ok(Box::new(OpenDevDirFile {
+                filestat: self.dir_filestat(devs, dir),
+            })),
+            (EntryKind::Symlink(_), _inode) => {
+                Err(Error::from(Errno::Loop).context("cannot open a symlink without following it"))
             }
-            (".", None) => Ok(Box::new(OpenDevDirFile)),
-            _ => Err(Error::not_found()),
         }
     }
 
-    async fn open_dir(
-        &self,
-        _symlink_follow: bool,
-        _path: &str,
-    ) -> Result<Box<dyn WasiDir>, Error> {
-        Err(Error::not_found().context("/dev/ does not have subdirectories"))
+    async fn open_dir(&self, symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        let devs = &self.computer.read().unwrap().devices;
+
+        match self.resolve(devs, path, symlink_follow)? {
+            (EntryKind::Dir(dir), _inode) => Ok(Box::new(DevicesDir {
+                dir,
+                ..self.clone()
+            })),
+            _ => Err(Error::not_dir()),
+        }
     }
 
     async fn create_dir(&self, _path: &str) -> Result<(), Error> {
@@ -116,45 +553,53 @@ impl WasiDir for DevicesDir {
         cursor: ReaddirCursor,
     ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
         let devs = &self.computer.read().unwrap().devices;
-        // TODO prepend ., ..
 
-        let inode_start = 1;
-        let cursor_start = 0;
-        let ethernet =
-            devs.ethernet_links
-                .clone()
-                .into_iter()
-                .enumerate()
-                .map(move |(idx, _link)| ReaddirEntity {
-                    next: ReaddirCursor::from(cursor_start + idx as u64 + 1),
-                    inode: inode_start + cursor_start + idx as u64,
-                    name: format!("ethernet{idx}"),
-                    filetype: FileType::CharacterDevice,
-                });
-
-        let cursor_start = devs.ethernet_links.len() as u64;
-        let wireless =
-            devs.wireless_links
-                .clone()
+        let parent_inode = if self.dir == 0 {
+            self.parent_inode
+        } else {
+            DevicesDir::dir_inode(devs, parent_dir(self.dir))
+        };
+
+        let mut entries = vec![
+            (
+                String::from("."),
+                DevicesDir::dir_inode(devs, self.dir),
+                FileType::Directory,
+            ),
+            (String::from(".."), parent_inode, FileType::Directory),
+        ];
+
+        entries.extend(
+            DevicesDir::entries(devs, self.dir)
                 .into_iter()
-                .enumerate()
-                .map(move |(idx, _link)| ReaddirEntity {
-                    next: ReaddirCursor::from(cursor_start + idx as u64 + 1),
-                    inode: inode_start + cursor_start + idx as u64,
-                    name: format!("wireless{idx}"),
-                    filetype: FileType::CharacterDevice,
-                });
+                .map(|entry| {
+                    let filetype = match entry.kind {
+                        EntryKind::Dir(_) => FileType::Directory,
+                        EntryKind::Device(_) => FileType::CharacterDevice,
+                        EntryKind::Symlink(_) => FileType::SymbolicLink,
+                    };
+                    (entry.name, entry.inode, filetype)
+                }),
+        );
+
+        let entries = entries
+            .into_iter()
+            .enumerate()
+            .map(|(pos, (name, inode, filetype))| ReaddirEntity {
+                next: ReaddirCursor::from(pos as u64 + 1),
+                inode,
+                name,
+                filetype,
+            })
+            .collect::<Vec<_>>();
 
         Ok(Box::new(
-            ethernet
-                .chain(wireless)
-                .map(Ok)
-                .skip(u64::from(cursor) as usize),
+            entries.into_iter().map(Ok).skip(u64::from(cursor) as usize),
         ))
     }
 
     async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), Error> {
-        Err(Error::not_supported().context("/dev/ does not support symlinks"))
+        Err(Error::perm().context("/dev/ is protected"))
     }
 
     async fn remove_dir(&self, _path: &str) -> Result<(), Error> {
@@ -162,24 +607,20 @@ impl WasiDir for DevicesDir {
     }
 
     async fn unlink_file(&self, _path: &str) -> Result<(), Error> {
-        Err(Error::not_supported().context("/dev/ does not support symlinks"))
+        Err(Error::perm().context("/dev/ is protected"))
     }
 
-    async fn read_link(&self, _path: &str) -> Result<PathBuf, Error> {
-        Err(Error::not_supported().context("/dev/ does not support symlinks"))
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        let devs = &self.computer.read().unwrap().devices;
+
+        match self.resolve(devs, path, false)? {
+            (EntryKind::Symlink(target), _inode) => Ok(PathBuf::from(target)),
+            _ => Err(Error::invalid_argument().context("not a symlink")),
+        }
     }
 
     async fn get_filestat(&self) -> Result<Filestat, Error> {
-        Ok(Filestat {
-            device_id: make_device_number(DEV_MAJOR, 0) as u64,
-            inode: 1,
-            filetype: FileType::Directory,
-            nlink: 0,
-            size: 0,
-            atim: None,
-            mtim: None,
-            ctim: None,
-        })
+        Ok(self.dir_filestat(&self.computer.read().unwrap().devices, self.dir))
     }
 
     async fn get_path_filestat(
@@ -187,17 +628,13 @@ impl WasiDir for DevicesDir {
         path: &str,
         follow_symlinks: bool,
     ) -> Result<Filestat, Error> {
-        self.open_file(
-            follow_symlinks,
-            path,
-            OFlags::empty(),
-            true,
-            false,
-            FdFlags::empty(),
-        )
-        .await?
-        .get_filestat()
-        .await
+        let devs = &self.computer.read().unwrap().devices;
+
+        match self.resolve(devs, path, follow_symlinks)? {
+            (EntryKind::Device(path), inode) => Ok(self.device_filestat(devs, &path, inode)),
+            (EntryKind::Dir(dir), _inode) => Ok(self.dir_filestat(devs, dir)),
+            (EntryKind::Symlink(target), inode) => Ok(self.symlink_filestat(devs, inode, &target)),
+        }
     }
 
     async fn rename(
@@ -229,16 +666,26 @@ impl WasiDir for DevicesDir {
     }
 }
 
-// TODO begin to fail when device is removed from the world
-struct OpenDuplexLinkFile {
-    link: AttachedDuplexLink,
-    device_number: u32,
+struct OpenCharDeviceFile {
+    device: Arc<dyn CharDevice>,
+    plug: Arc<Plug>,
+    node: DeviceNode,
     read: bool,
     write: bool,
 }
 
+impl OpenCharDeviceFile {
+    fn check_plugged(&self) -> Result<(), Error> {
+        if self.plug.is_unplugged() {
+            Err(Error::io().context("device was unplugged"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[async_trait]
-impl WasiFile for OpenDuplexLinkFile {
+impl WasiFile for OpenCharDeviceFile {
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -255,21 +702,12 @@ impl WasiFile for OpenDuplexLinkFile {
             Ok(())
         } else {
             Err(Error::not_supported()
-                .context("network link devices do not support flags other than append"))
+                .context("character devices do not support flags other than append"))
         }
     }
 
     async fn get_filestat(&self) -> Result<Filestat, Error> {
-        Ok(Filestat {
-            device_id: self.device_number as u64,
-            inode: 1,
-            filetype: FileType::CharacterDevice,
-            nlink: 0,
-            size: 0,
-            atim: None,
-            mtim: None,
-            ctim: None,
-        })
+        Ok(self.node.filestat(0))
     }
 
     async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
@@ -277,7 +715,8 @@ impl WasiFile for OpenDuplexLinkFile {
             return Err(Error::badf().context("file opened as writeonly"));
         }
 
-        Ok(self.link.read_buf().buf.read_vectored(bufs)? as u64)
+        self.check_plugged()?;
+        Ok(self.device.read_buf().read_vectored(bufs) as u64)
     }
 
     async fn write_vectored<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
@@ -285,16 +724,28 @@ impl WasiFile for OpenDuplexLinkFile {
             return Err(Error::badf().context("file opened as readonly"));
         }
 
-        let mut buf = self.link.write_buf();
-        let n = buf.buf.write_vectored(bufs)?;
-        buf.on_send.notify(usize::MAX);
+        self.check_plugged()?;
+
+        if let Some(max_frame_size) = self.device.max_frame_size() {
+            let len: usize = bufs.iter().map(|buf| buf.len()).sum();
+            if len > max_frame_size {
+                return Err(Error::from(Errno::Msgsize).context("frame exceeds maximum frame size"));
+            }
+        }
+
+        let n = self.device.write_vectored(bufs);
+
+        if n == 0 && bufs.iter().any(|buf| !buf.is_empty()) {
+            return Err(Error::from(Errno::Again).context("device buffer is full"));
+        }
 
         Ok(n as u64)
     }
 
     fn num_ready_bytes(&self) -> Result<u64, Error> {
         if self.read {
-            Ok(self.link.read_buf().buf.len() as u64)
+            self.check_plugged()?;
+            Ok(self.device.read_buf().num_ready_bytes() as u64)
         } else {
             Err(Error::badf().context("file opened as writeonly"))
         }
@@ -317,7 +768,105 @@ impl WasiFile for OpenDuplexLinkFile {
     }
 }
 
-struct OpenDevDirFile;
+/// An fd open to a [`Device`] plugged in by the host.
+struct OpenDeviceFile {
+    device: Arc<dyn Device>,
+    plug: Arc<Plug>,
+    node: DeviceNode,
+    read: bool,
+    write: bool,
+}
+
+impl OpenDeviceFile {
+    fn check_plugged(&self) -> Result<(), Error> {
+        if self.plug.is_unplugged() {
+            Err(Error::io().context("device was unplugged"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl WasiFile for OpenDeviceFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::CharacterDevice)
+    }
+
+    async fn get_fdflags(&self) -> Result<FdFlags, Error> {
+        Ok(FdFlags::empty())
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        let mut filestat = self.node.filestat(0);
+
+        self.device.filestat(&mut filestat);
+        Ok(filestat)
+    }
+
+    async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        if !self.read {
+            return Err(Error::badf().context("file opened as writeonly"));
+        }
+
+        self.check_plugged()?;
+        let n = self.device.read(bufs)?;
+
+        if n == 0 && bufs.iter().any(|buf| !buf.is_empty()) {
+            return Err(Error::from(Errno::Again).context("device is not ready for read"));
+        }
+
+        Ok(n as u64)
+    }
+
+    async fn write_vectored<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        if !self.write {
+            return Err(Error::badf().context("file opened as readonly"));
+        }
+
+        self.check_plugged()?;
+        let n = self.device.write(bufs)?;
+
+        if n == 0 && bufs.iter().any(|buf| !buf.is_empty()) {
+            return Err(Error::from(Errno::Again).context("device is not ready for write"));
+        }
+
+        Ok(n as u64)
+    }
+
+    fn num_ready_bytes(&self) -> Result<u64, Error> {
+        if self.read {
+            self.check_plugged()?;
+            Ok(self.device.num_ready_bytes() as u64)
+        } else {
+            Err(Error::badf().context("file opened as writeonly"))
+        }
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        if self.read {
+            Ok(())
+        } else {
+            Err(Error::badf().context("file opened as writeonly"))
+        }
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        if self.write {
+            Ok(())
+        } else {
+            Err(Error::badf().context("file opened as readonly"))
+        }
+    }
+}
+
+struct OpenDevDirFile {
+    filestat: Filestat,
+}
 
 #[async_trait]
 impl WasiFile for OpenDevDirFile {
@@ -337,6 +886,10 @@ impl WasiFile for OpenDevDirFile {
         Err(Error::not_supported().context("/dev/ is a special directory"))
     }
 
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(self.filestat.clone())
+    }
+
     async fn readable(&self) -> Result<(), Error> {
         Ok(())
     }
@@ -345,3 +898,174 @@ impl WasiFile for OpenDevDirFile {
         Err(Error::not_supported().context("/dev/ is readonly"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::AttachedDuplexLink;
+    use crate::Position;
+    use uuid::Uuid;
+
+    /// `/dev` of a computer with one ethernet link plugged in.
+    fn dev_dir() -> DevicesDir {
+        let mut devices = Devices::default();
+        let (link, _other_end) = AttachedDuplexLink::new_pair();
+        devices.add_ethernet(link);
+
+        let computer = Computer {
+            id: Uuid::nil(),
+            position: Position::default(),
+            cpu_speed: None,
+            ram_size: None,
+            devices,
+        };
+
+        DevicesDir::new(Arc::new(RwLock::new(computer)), 1)
+    }
+
+    fn subdir(dir: &DevicesDir, path: &str) -> DevicesDir {
+        DevicesDir {
+            dir: DIRS.iter().position(|dir| *dir == path).unwrap(),
+            ..dir.clone()
+        }
+    }
+
+    fn resolve(dir: &DevicesDir, path: &str, follow_symlinks: bool) -> Result<EntryKind, Error> {
+        let devs = &dir.computer.read().unwrap().devices;
+        dir.resolve(devs, path, follow_symlinks)
+            .map(|(kind, _inode)| kind)
+    }
+
+    fn errno(result: Result<EntryKind, Error>) -> Errno {
+        match result {
+            Ok(_) => panic!("path resolved"),
+            Err(e) => e.downcast().unwrap(),
+        }
+    }
+
+    #[test]
+    fn resolves_net_symlinks() {
+        let dev = dev_dir();
+
+        assert!(matches!(
+            resolve(&dev, "net/ethernet0", true),
+            Ok(EntryKind::Device(path)) if path == "ethernet0"
+        ));
+        assert!(matches!(
+            resolve(&dev, "net/ethernet0", false),
+            Ok(EntryKind::Symlink(target)) if target == "../ethernet0"
+        ));
+        assert!(matches!(
+            resolve(&subdir(&dev, "net"), "./ethernet0", true),
+            Ok(EntryKind::Device(path)) if path == "ethernet0"
+        ));
+        assert_eq!(errno(resolve(&dev, "net/ethernet1", true)), Errno::Noent);
+    }
+
+    #[test]
+    fn resolves_char_symlinks() {
+        let dev = dev_dir();
+        let devs = &dev.computer.read().unwrap().devices;
+
+        for (path, number) in DevicesDir::device_files(devs) {
+            let (major, minor) = split_device_number(number);
+            let link = format!("char/{major}:{minor}");
+
+            if path == "events" {
+                assert_eq!(errno(resolve(&dev, &link, true)), Errno::Noent);
+            } else {
+                assert!(matches!(
+                    resolve(&dev, &link, true),
+                    Ok(EntryKind::Device(target)) if target == path
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn dot_dot_stays_in_dev() {
+        let dev = dev_dir();
+
+        assert!(matches!(
+            resolve(&dev, "net/..", true),
+            Ok(EntryKind::Dir(0))
+        ));
+        assert!(matches!(
+            resolve(&subdir(&dev, "disk/by-id"), "../..", true),
+            Ok(EntryKind::Dir(0))
+        ));
+        assert!(matches!(
+            resolve(&subdir(&dev, "input"), "../tty0", true),
+            Ok(EntryKind::Device(path)) if path == "tty0"
+        ));
+
+        for (dir, path) in [
+            ("", ".."),
+            ("", "net/../.."),
+            ("", "./../dev/tty0"),
+            ("net", "../.."),
+            ("disk/by-id", "../../.."),
+        ] {
+            assert_eq!(errno(resolve(&subdir(&dev, dir), path, true)), Errno::Perm);
+        }
+    }
+
+    #[test]
+    fn symlinks_do_not_lead_through_devices() {
+        let dev = dev_dir();
+
+        // Every symlink leads to a device, so none can form a loop, and a path can only go on past
+        // one if it names a directory
+        assert_eq!(
+            errno(resolve(&dev, "net/ethernet0/..", true)),
+            Errno::Notdir
+        );
+        assert_eq!(errno(resolve(&dev, "char/../tty0/x", true)), Errno::Notdir);
+    }
+
+    #[tokio::test]
+    async fn opening_symlink_without_following_it_is_a_loop() {
+        let dev = dev_dir();
+
+        let result = dev
+            .open_file(
+                false,
+                "net/ethernet0",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty(),
+            )
+            .await;
+        match result {
+            Ok(_) => panic!("symlink opened"),
+            Err(e) => assert_eq!(e.downcast().unwrap(), Errno::Loop),
+        }
+
+        assert!(dev
+            .open_file(
+                true,
+                "net/ethernet0",
+                OFlags::empty(),
+                true,
+                false,
+                FdFlags::empty()
+            )
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn events_indices_are_reused_once_closed() {
+        let mut devices = Devices::default();
+
+        let (first, first_queue) = devices.subscribe_events().unwrap();
+        let (second, _second_queue) = devices.subscribe_events().unwrap();
+        assert_eq!((first, second), (0, 1));
+
+        drop(first_queue);
+        let (third, _third_queue) = devices.subscribe_events().unwrap();
+        assert_eq!(third, 0);
+        assert_eq!(devices.subscribe_events().unwrap().0, 2);
+    }
+}
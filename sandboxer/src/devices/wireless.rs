@@ -0,0 +1,217 @@
+use crate::devices::{Buffer, CharDevice, DEFAULT_LINK_CAPACITY};
+use crate::Position;
+use event_listener::EventListener;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::IoSlice;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+/// Largest frame a radio can transmit by default, matching the 802.11 MSDU limit.
+pub const DEFAULT_WIRELESS_FRAME_SIZE: usize = 2304;
+
+/// Properties of a wireless medium, applied to every radio attached to it.
+#[derive(Debug, Clone)]
+pub struct WirelessConfig {
+    /// Maximum number of bytes each radio buffers before further transmissions to it are dropped.
+    pub capacity: usize,
+    /// Largest frame which can be transmitted.
+    pub max_frame_size: usize,
+    /// Distance within which every frame is received.
+    pub reliable_range: f64,
+    /// Distance beyond which no frame is received. Between the reliable range and this range, the
+    /// chance of a frame being lost rises linearly from nothing to certain.
+    pub range: f64,
+    /// Seed for the random loss of frames near the edge of the range. Random if not set.
+    pub seed: Option<u64>,
+}
+
+impl Default for WirelessConfig {
+    fn default() -> Self {
+        WirelessConfig {
+            capacity: DEFAULT_LINK_CAPACITY,
+            max_frame_size: DEFAULT_WIRELESS_FRAME_SIZE,
+            reliable_range: f64::INFINITY,
+            range: f64::INFINITY,
+            seed: None,
+        }
+    }
+}
+
+struct Medium {
+    config: WirelessConfig,
+    radios: Mutex<Vec<Weak<Radio>>>,
+    rng: Mutex<StdRng>,
+}
+
+impl Medium {
+    /// Decides whether a frame sent across the given distance is received. A distance which is not
+    /// finite, such as one between radios placed at NaN or infinite positions, is out of range.
+    fn is_received(&self, distance: f64) -> bool {
+        let WirelessConfig {
+            reliable_range,
+            range,
+            ..
+        } = self.config;
+
+        if !distance.is_finite() {
+            false
+        } else if distance <= reliable_range {
+            true
+        } else if distance > range {
+            false
+        } else {
+            let loss = (distance - reliable_range) / (range - reliable_range);
+            !self.rng.lock().unwrap().gen_bool(loss.clamp(0.0, 1.0))
+        }
+    }
+}
+
+struct Radio {
+    rx: Mutex<Buffer>,
+    position: Mutex<Position>,
+}
+
+/// A shared broadcast medium, such as a single Wi-Fi channel. Every frame transmitted by a radio
+/// attached to the medium is received by every other radio attached to it.
+#[derive(Clone)]
+pub struct WirelessMedium {
+    shared: Arc<Medium>,
+}
+
+impl WirelessMedium {
+    pub fn new() -> WirelessMedium {
+        Self::with_config(WirelessConfig::default())
+    }
+
+    /// # Panics
+    /// Panics if the maximum frame size exceeds the radio capacity, or if the reliable range
+    /// exceeds the range.
+    pub fn with_config(config: WirelessConfig) -> WirelessMedium {
+        assert!(
+            config.max_frame_size <= config.capacity,
+            "maximum frame size exceeds radio capacity"
+        );
+        assert!(
+            config.reliable_range <= config.range,
+            "reliable range exceeds range"
+        );
+
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        WirelessMedium {
+            shared: Arc::new(Medium {
+                config,
+                radios: Mutex::new(Vec::new()),
+                rng: Mutex::new(rng),
+            }),
+        }
+    }
+
+    /// Creates a new radio tuned to this medium, ready to be attached to a computer.
+    pub fn new_radio(&self) -> AttachedRadio {
+        let config = &self.shared.config;
+        let radio = Arc::new(Radio {
+            rx: Mutex::new(Buffer::new(
+                config.capacity,
+                Some(config.max_frame_size),
+                None,
+            )),
+            position: Mutex::new(Position::default()),
+        });
+
+        self.shared
+            .radios
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&radio));
+
+        AttachedRadio {
+            radio,
+            medium: self.shared.clone(),
+        }
+    }
+}
+
+impl Default for WirelessMedium {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct AttachedRadio {
+    radio: Arc<Radio>,
+    medium: Arc<Medium>,
+}
+
+impl AttachedRadio {
+    pub fn position(&self) -> Position {
+        *self.radio.position.lock().unwrap()
+    }
+
+    pub(crate) fn set_position(&self, position: Position) {
+        *self.radio.position.lock().unwrap() = position;
+    }
+}
+
+impl CharDevice for AttachedRadio {
+    fn read_buf(&self) -> MutexGuard<'_, Buffer> {
+        self.radio.rx.lock().unwrap()
+    }
+
+    /// Broadcasts the bytes as one frame to every other radio in range on the medium. Radios whose
+    /// buffers are full miss the frame, as the medium never waits for receivers.
+    fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> usize {
+        let frame: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        let position = self.position();
+        let mut radios = self.medium.radios.lock().unwrap();
+        radios.retain(|radio| radio.strong_count() > 0);
+
+        for radio in radios.iter().filter_map(Weak::upgrade) {
+            let distance = position.distance(&radio.position.lock().unwrap());
+
+            if !Arc::ptr_eq(&radio, &self.radio) && self.medium.is_received(distance) {
+                radio
+                    .rx
+                    .lock()
+                    .unwrap()
+                    .write_vectored(&[IoSlice::new(&frame)]);
+            }
+        }
+
+        frame.len()
+    }
+
+    fn max_frame_size(&self) -> Option<usize> {
+        Some(self.medium.config.max_frame_size)
+    }
+
+    fn is_ready_for_write(&self) -> bool {
+        true
+    }
+
+    fn listen_for_write(&self) -> Option<EventListener> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_distances_are_out_of_range() {
+        let medium = WirelessMedium::with_config(WirelessConfig {
+            reliable_range: 10.0,
+            seed: Some(0),
+            ..WirelessConfig::default()
+        });
+
+        assert!(medium.shared.is_received(5.0));
+        assert!(!medium.shared.is_received(f64::NAN));
+        assert!(!medium.shared.is_received(f64::INFINITY));
+    }
+}
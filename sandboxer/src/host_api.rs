@@ -6,17 +6,47 @@ use wasmtime::Caller;
 use wasmtime::Linker;
 
 pub fn add_exports(linker: &mut Linker<ComputerVmState>) -> Result<()> {
-    linker.func_wrap3_async("event", "wait_until_ready", device::wait_until_ready)?;
+    linker.func_wrap4_async("event", "wait_until_ready", device::wait_until_ready)?;
+    linker.func_wrap("display", "framebuffer_info", display::framebuffer_info)?;
     Ok(())
 }
 
+mod display {
+    use super::*;
+    use anyhow::Context;
+    use wasmtime::Extern;
+
+    pub fn framebuffer_info(mut caller: Caller<'_, ComputerVmState>, info_ptr: i64) -> Result<i64> {
+        let mem = match caller.get_export("memory") {
+            Some(Extern::Memory(mem)) => mem,
+            _ => anyhow::bail!("failed to find host memory"),
+        };
+
+        let info = caller
+            .data()
+            .computer
+            .read()
+            .unwrap()
+            .devices
+            .framebuffer()
+            .info();
+
+        mem.write(&mut caller, info_ptr as usize, bytemuck::bytes_of(&info))
+            .context("failed to store framebuffer info")?;
+
+        Ok(0)
+    }
+}
+
 mod device {
     use super::*;
-    use crate::devices::virtual_fs::decompose_device;
+    use crate::Computer;
     use anyhow::Context;
-    use futures::future::Either;
-    use host_api_sys::{Interest, Ready};
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use host_api_sys::{Interest, InterestFlags, Ready};
     use std::future::Future;
+    use std::time::Duration;
     use wasmtime::Extern;
 
     pub fn wait_until_ready<'a>(
@@ -24,6 +54,7 @@ mod device {
         interests_ptr: i64,
         ready_ptr: i64,
         len: i64,
+        timeout_ns: i64,
     ) -> Box<dyn Future<Output = Result<i64>> + Send + 'a> {
         Box::new(async move {
             let mem = match caller.get_export("memory") {
@@ -45,65 +76,54 @@ mod device {
                 Vec::from(interests_guest)
             };
 
+            // Devices which have been unplugged are always ready, so that the guest finds out
+            // when it next reads or writes
             let mut devices = Vec::with_capacity(interests.len());
 
             {
                 let vm = caller.data_mut();
                 for interest in &interests {
                     let dev = vm.wasi.fd_filestat_get(Fd::from(interest.fd)).await?.dev;
-                    let computer = vm.computer.read().unwrap();
+                    devices.push(dev);
+                }
+            }
+
+            let computer = caller.data().computer.clone();
+            let wait_for_ready = async move {
+                // Loop to avoid reporting spurious wakeups as ready
+                loop {
+                    // Listen before checking, so that nothing becoming ready in between is missed.
+                    // Anything found ready is returned before waiting, so only waits which can be
+                    // pending are ever waited on.
+                    let wait = {
+                        let computer = computer.read().unwrap();
+                        let wait = wait_for_any(&computer, &devices, &interests);
 
-                    match decompose_device(dev) {
-                        Some((dev_type, dev_idx))
-                            if !computer.devices.contains(dev_type, dev_idx) =>
-                        {
-                            anyhow::bail!("Unknown device: type {dev_type:?}, idx {dev_idx}")
+                        let ready = ready_interests(&computer, &devices, &interests);
+                        if !ready.is_empty() {
+                            break ready;
                         }
-                        _ => (),
+                        wait
+                    };
+
+                    if wait.is_empty() {
+                        // No flags were given, so nothing can ever become ready
+                        futures::future::pending::<()>().await;
                     }
 
-                    devices.push(dev);
+                    futures::future::select_all(wait).await;
                 }
-            }
+            };
 
-            let wait = devices.iter().map(|device| {
-                let computer = caller.data().computer.read().unwrap();
-                match decompose_device(*device) {
-                    // Is a device managed by /dev/
-                    Some((dev_type, dev_idx)) => Either::Left(
-                        computer
-                            .devices
-                            .wait_until_ready_for_read(dev_type, dev_idx)
-                            .unwrap(),
-                    ),
-                    // Is a regular file, so it is always ready for read
-                    None => Either::Right(futures::future::ready(())),
+            let ready = if timeout_ns < 0 {
+                wait_for_ready.await
+            } else {
+                match tokio::time::timeout(Duration::from_nanos(timeout_ns as u64), wait_for_ready)
+                    .await
+                {
+                    Ok(ready) => ready,
+                    Err(_) => return Ok(0),
                 }
-            });
-
-            futures::future::select_all(wait).await;
-
-            let ready: Vec<Ready> = {
-                let computer = caller.data().computer.read().unwrap();
-                devices
-                    .iter()
-                    .zip(interests.iter())
-                    .filter(|(dev, _interest)| {
-                        match decompose_device(**dev) {
-                            // Is a device managed by /dev/
-                            Some((dev_type, dev_idx)) => computer
-                                .devices
-                                .is_ready_for_read(dev_type, dev_idx)
-                                .unwrap(),
-                            // Is a regular file, so it is always ready for read
-                            None => true,
-                        }
-                    })
-                    .map(|(_, interest)| Ready {
-                        fd: interest.fd,
-                        interest_flags: interest.interest_flags,
-                    })
-                    .collect()
             };
 
             let ready_bytes = mem
@@ -124,4 +144,86 @@ mod device {
             Ok(ready_len as i64)
         })
     }
+
+    fn wait_for_any(
+        computer: &Computer,
+        devices: &[u64],
+        interests: &[Interest],
+    ) -> Vec<BoxFuture<'static, ()>> {
+        devices
+            .iter()
+            .zip(interests.iter())
+            .flat_map(|(device, interest)| {
+                let flags = interest.flags();
+                let mut waits: Vec<BoxFuture<'static, ()>> = Vec::with_capacity(2);
+
+                match computer.devices.decompose_device(*device) {
+                    // Is a device managed by /dev/
+                    Some((dev_type, dev_idx)) => {
+                        if flags.contains(InterestFlags::READ) {
+                            match computer
+                                .devices
+                                .wait_until_ready_for_read(dev_type, dev_idx)
+                            {
+                                Some(wait) => waits.push(wait.boxed()),
+                                None => waits.push(futures::future::ready(()).boxed()),
+                            }
+                        }
+
+                        if flags.contains(InterestFlags::WRITE) {
+                            match computer
+                                .devices
+                                .wait_until_ready_for_write(dev_type, dev_idx)
+                            {
+                                Some(wait) => waits.push(wait.boxed()),
+                                None => waits.push(futures::future::ready(()).boxed()),
+                            }
+                        }
+                    }
+                    // Is a regular file, so it is always ready for whatever it is waited on for
+                    None if !flags.is_empty() => waits.push(futures::future::ready(()).boxed()),
+                    None => {}
+                }
+
+                waits
+            })
+            .collect()
+    }
+
+    fn ready_interests(computer: &Computer, devices: &[u64], interests: &[Interest]) -> Vec<Ready> {
+        devices
+            .iter()
+            .zip(interests.iter())
+            .filter_map(|(dev, interest)| {
+                let flags = match computer.devices.decompose_device(*dev) {
+                    // Is a device managed by /dev/
+                    Some((dev_type, dev_idx)) => {
+                        let mut ready = InterestFlags::empty();
+                        ready.set(
+                            InterestFlags::READ,
+                            computer
+                                .devices
+                                .is_ready_for_read(dev_type, dev_idx)
+                                .unwrap_or(true),
+                        );
+                        ready.set(
+                            InterestFlags::WRITE,
+                            computer
+                                .devices
+                                .is_ready_for_write(dev_type, dev_idx)
+                                .unwrap_or(true),
+                        );
+                        ready & interest.flags()
+                    }
+                    // Is a regular file, so it is always ready
+                    None => interest.flags(),
+                };
+
+                (!flags.is_empty()).then_some(Ready {
+                    fd: interest.fd,
+                    interest_flags: flags.bits(),
+                })
+            })
+            .collect()
+    }
 }
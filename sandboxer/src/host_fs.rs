@@ -0,0 +1,350 @@
+use crate::devices::numbers::{make_device_number, MINOR_COUNT};
+use async_trait::async_trait;
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{
+    Advice, FdFlags, FileType, Filestat, OFlags, RiFlags, RoFlags, SdFlags, SiFlags,
+};
+use wasi_common::{Error, ErrorExt, SystemTimeSpec, WasiDir, WasiFile};
+
+/// Host device ids and inodes seen by one computer. Each device is given its own minor under the
+/// computer's `host` major, in the order they are first seen, so that host files can never be
+/// mistaken for simulated devices. Inodes are scrambled by a bijection salted with the computer's
+/// id, so that they stay unique within each device without keeping a table of them, while the
+/// host's inode numbers are not exposed as-is.
+pub(crate) struct HostDevices {
+    major: u16,
+    /// Ids of the devices seen, with their minor as index.
+    devices: Mutex<Vec<u64>>,
+    /// Odd multipliers of the inode bijection, which are invertible modulo 2^64.
+    inode_keys: [u64; 2],
+}
+
+impl HostDevices {
+    pub(crate) fn new(major: u16, computer_id: Uuid) -> HostDevices {
+        let salt = computer_id.as_u128();
+        HostDevices {
+            major,
+            devices: Mutex::new(Vec::new()),
+            inode_keys: [(salt >> 64) as u64 | 1, salt as u64 | 1],
+        }
+    }
+
+    fn remap(&self, mut filestat: Filestat) -> Result<Filestat, Error> {
+        let minor = self.minor(filestat.device_id)?;
+
+        filestat.device_id = make_device_number(self.major, minor as u32).unwrap() as u64;
+        filestat.inode = self.remap_inode(filestat.inode);
+        Ok(filestat)
+    }
+
+    /// The inode a host inode is seen as. Each step is a bijection which maps 0 to itself, so
+    /// valid inodes are never mapped to 0.
+    pub(crate) fn remap_inode(&self, inode: u64) -> u64 {
+        let mut inode = inode.wrapping_mul(self.inode_keys[0]);
+        inode ^= inode >> 32;
+        inode.wrapping_mul(self.inode_keys[1])
+    }
+
+    /// The minor of the host device, or an error if it is new and every minor has been given out.
+    fn minor(&self, id: u64) -> Result<usize, Error> {
+        let mut devices = self.devices.lock().unwrap();
+        match devices.iter().position(|device| *device == id) {
+            Some(minor) => Ok(minor),
+            None if devices.len() < MINOR_COUNT => {
+                devices.push(id);
+                Ok(devices.len() - 1)
+            }
+            None => Err(Error::overflow().context("too many host devices")),
+        }
+    }
+}
+
+/// A directory on the host's filesystem, as seen by a guest.
+pub(crate) struct HostDir {
+    inner: Box<dyn WasiDir>,
+    devices: Arc<HostDevices>,
+}
+
+impl HostDir {
+    pub(crate) fn new(inner: Box<dyn WasiDir>, devices: Arc<HostDevices>) -> HostDir {
+        HostDir { inner, devices }
+    }
+
+    /// The wrapped directory of another host directory, which the wrapped directories need to
+    /// recognise each other by.
+    fn unwrap_other(other: &dyn WasiDir) -> Result<&dyn WasiDir, Error> {
+        other
+            .as_any()
+            .downcast_ref::<HostDir>()
+            .map(|dir| &*dir.inner)
+            .ok_or_else(|| Error::badf().context("not a host directory"))
+    }
+}
+
+#[async_trait]
+impl WasiDir for HostDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let inner = self
+            .inner
+            .open_file(symlink_follow, path, oflags, read, write, fdflags)
+            .await?;
+
+        Ok(Box::new(HostFile {
+            inner,
+            devices: self.devices.clone(),
+        }))
+    }
+
+    async fn open_dir(&self, symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        let inner = self.inner.open_dir(symlink_follow, path).await?;
+        Ok(Box::new(HostDir::new(inner, self.devices.clone())))
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<(), Error> {
+        self.inner.create_dir(path).await
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let devices = self.devices.clone();
+        let entries = self.inner.readdir(cursor).await?;
+
+        Ok(Box::new(entries.map(move |entry| {
+            let mut entry = entry?;
+            entry.inode = devices.remap_inode(entry.inode);
+            Ok(entry)
+        })))
+    }
+
+    async fn symlink(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
+        self.inner.symlink(old_path, new_path).await
+    }
+
+    async fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        self.inner.remove_dir(path).await
+    }
+
+    async fn unlink_file(&self, path: &str) -> Result<(), Error> {
+        self.inner.unlink_file(path).await
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.inner.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.devices.remap(self.inner.get_filestat().await?)
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        let filestat = self.inner.get_path_filestat(path, follow_symlinks).await?;
+        self.devices.remap(filestat)
+    }
+
+    async fn rename(
+        &self,
+        path: &str,
+        dest_dir: &dyn WasiDir,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        let dest_dir = HostDir::unwrap_other(dest_dir)?;
+        self.inner.rename(path, dest_dir, dest_path).await
+    }
+
+    async fn hard_link(
+        &self,
+        path: &str,
+        target_dir: &dyn WasiDir,
+        target_path: &str,
+    ) -> Result<(), Error> {
+        let target_dir = HostDir::unwrap_other(target_dir)?;
+        self.inner.hard_link(path, target_dir, target_path).await
+    }
+
+    async fn set_times(
+        &self,
+        path: &str,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+        follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        self.inner
+            .set_times(path, atime, mtime, follow_symlinks)
+            .await
+    }
+}
+
+/// A file on the host's filesystem, as seen by a guest.
+struct HostFile {
+    inner: Box<dyn WasiFile>,
+    devices: Arc<HostDevices>,
+}
+
+#[async_trait]
+impl WasiFile for HostFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        self.inner.get_filetype().await
+    }
+
+    fn pollable(&self) -> Option<rustix::fd::BorrowedFd<'_>> {
+        self.inner.pollable()
+    }
+
+    fn isatty(&self) -> bool {
+        self.inner.isatty()
+    }
+
+    async fn sock_accept(&self, fdflags: FdFlags) -> Result<Box<dyn WasiFile>, Error> {
+        self.inner.sock_accept(fdflags).await
+    }
+
+    async fn sock_recv<'a>(
+        &self,
+        ri_data: &mut [IoSliceMut<'a>],
+        ri_flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        self.inner.sock_recv(ri_data, ri_flags).await
+    }
+
+    async fn sock_send<'a>(
+        &self,
+        si_data: &[IoSlice<'a>],
+        si_flags: SiFlags,
+    ) -> Result<u64, Error> {
+        self.inner.sock_send(si_data, si_flags).await
+    }
+
+    async fn sock_shutdown(&self, how: SdFlags) -> Result<(), Error> {
+        self.inner.sock_shutdown(how).await
+    }
+
+    async fn datasync(&self) -> Result<(), Error> {
+        self.inner.datasync().await
+    }
+
+    async fn sync(&self) -> Result<(), Error> {
+        self.inner.sync().await
+    }
+
+    async fn get_fdflags(&self) -> Result<FdFlags, Error> {
+        self.inner.get_fdflags().await
+    }
+
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        self.inner.set_fdflags(flags).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.devices.remap(self.inner.get_filestat().await?)
+    }
+
+    async fn set_filestat_size(&self, size: u64) -> Result<(), Error> {
+        self.inner.set_filestat_size(size).await
+    }
+
+    async fn advise(&self, offset: u64, len: u64, advice: Advice) -> Result<(), Error> {
+        self.inner.advise(offset, len, advice).await
+    }
+
+    async fn allocate(&self, offset: u64, len: u64) -> Result<(), Error> {
+        self.inner.allocate(offset, len).await
+    }
+
+    async fn set_times(
+        &self,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.inner.set_times(atime, mtime).await
+    }
+
+    async fn read_vectored<'a>(&self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        self.inner.read_vectored(bufs).await
+    }
+
+    async fn read_vectored_at<'a>(
+        &self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.inner.read_vectored_at(bufs, offset).await
+    }
+
+    async fn write_vectored<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        self.inner.write_vectored(bufs).await
+    }
+
+    async fn write_vectored_at<'a>(&self, bufs: &[IoSlice<'a>], offset: u64) -> Result<u64, Error> {
+        self.inner.write_vectored_at(bufs, offset).await
+    }
+
+    async fn seek(&self, pos: SeekFrom) -> Result<u64, Error> {
+        self.inner.seek(pos).await
+    }
+
+    async fn peek(&self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.inner.peek(buf).await
+    }
+
+    fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.inner.num_ready_bytes()
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        self.inner.readable().await
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        self.inner.writable().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn inodes_are_remapped_without_collisions() {
+        let devices = HostDevices::new(
+            1,
+            Uuid::from_u128(0x0123_4567_89ab_cdef_fedc_ba98_7654_3210),
+        );
+
+        let host_inodes = (1..10_000).chain((0..64).map(|shift| 1 << shift));
+        let inodes: HashSet<_> = host_inodes
+            .clone()
+            .map(|inode| devices.remap_inode(inode))
+            .collect();
+        assert_eq!(inodes.len(), host_inodes.collect::<HashSet<_>>().len());
+        assert!(!inodes.contains(&0));
+        assert_eq!(devices.remap_inode(0), 0);
+    }
+}
@@ -1,28 +1,93 @@
 pub mod devices;
 mod host_api;
+mod host_fs;
+pub mod memory;
+pub mod output;
+pub mod throttle;
 
-use crate::devices::{virtual_fs::DevicesDir, AttachedDuplexLink, Devices};
+use crate::devices::framebuffer::Framebuffer;
+use crate::devices::input::InputDevice;
+use crate::devices::stdin::{Stdin, StdinFile};
+use crate::devices::tty::Tty;
+use crate::devices::wireless::AttachedRadio;
+use crate::devices::{virtual_fs::DevicesDir, AttachedDuplexLink, Device, Devices};
+use crate::host_fs::{HostDevices, HostDir};
+use crate::memory::{MemoryUsage, RamLimiter};
+use crate::output::{output_stream, OutputStream};
+use crate::throttle::{CpuUsage, InHostCall, Throttled};
 use anyhow::Result;
-use std::collections::VecDeque;
-use std::io::BufReader;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
-use utf8::BufReadDecoder;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
 use uuid::Uuid;
-use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasi_common::pipe::WritePipe;
 use wasmtime::{Config, Engine, Func, Linker, Module, Store, Val};
-use wasmtime_wasi::sync::WasiCtxBuilder;
+use wasmtime_wasi::sync::{self, WasiCtxBuilder};
 use wasmtime_wasi::{ambient_authority, Dir, WasiCtx};
 
+/// Length of the tick over which a computer's CPU speed is measured.
+pub const CPU_TICK: Duration = Duration::from_millis(10);
+
+/// How often the engine's epoch advances.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// Number of epoch ticks a guest may run for before yielding to let other computers run.
+const EPOCH_TIME_SLICE: u64 = 1;
+
+/// The engine guests are run on, whose guests are preempted periodically, so that a guest stuck in
+/// a loop cannot stall the runtime, and which counts the instructions each guest runs. The engine
+/// is shared by the whole process, so that a single background thread advances its epoch.
 pub fn our_engine() -> Engine {
-    Engine::new(
-        Config::new().async_support(true), // .epoch_interruption(true) // TODO epoch interruption
-    )
-    .unwrap()
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+    ENGINE
+        .get_or_init(|| {
+            let engine = Engine::new(
+                Config::new()
+                    .async_support(true)
+                    .epoch_interruption(true)
+                    .consume_fuel(true),
+            )
+            .unwrap();
+
+            let ticker = engine.clone();
+            std::thread::Builder::new()
+                .name("epoch-ticker".to_string())
+                .spawn(move || loop {
+                    std::thread::sleep(EPOCH_TICK);
+                    ticker.increment_epoch();
+                })
+                .unwrap();
+
+            engine
+        })
+        .clone()
+}
+
+/// A point in the game world, in metres.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Position {
+    pub fn new(x: f64, y: f64, z: f64) -> Position {
+        Position { x, y, z }
+    }
+
+    pub fn distance(&self, other: &Position) -> f64 {
+        let (dx, dy, dz) = (self.x - other.x, self.y - other.y, self.z - other.z);
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
 }
 
 pub struct Computer {
     id: Uuid,
+    position: Position,
+    cpu_speed: Option<u64>,
+    ram_size: Option<usize>,
     devices: Devices,
 }
 
@@ -31,6 +96,9 @@ impl Computer {
     pub fn create() -> Result<Computer> {
         let computer = Computer {
             id: Uuid::new_v4(),
+            position: Position::default(),
+            cpu_speed: None,
+            ram_size: None,
             devices: Devices::default(),
         };
 
@@ -39,10 +107,62 @@ impl Computer {
         Ok(computer)
     }
 
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
     pub fn devices_mut(&mut self) -> &mut Devices {
         &mut self.devices
     }
 
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Moves the computer, along with all of its radios.
+    pub fn set_position(&mut self, position: Position) {
+        self.position = position;
+        self.devices.move_radios(position);
+    }
+
+    /// Number of wasm instructions the computer can run per [`CPU_TICK`], or `None` if it runs as
+    /// fast as the host allows.
+    pub fn cpu_speed(&self) -> Option<u64> {
+        self.cpu_speed
+    }
+
+    /// Sets the number of wasm instructions the computer can run per [`CPU_TICK`]. Takes effect the
+    /// next time the computer is launched.
+    ///
+    /// # Panics
+    /// Panics if the speed is zero.
+    pub fn set_cpu_speed(&mut self, cpu_speed: Option<u64>) {
+        assert_ne!(cpu_speed, Some(0), "CPU speed must be non-zero");
+        self.cpu_speed = cpu_speed;
+    }
+
+    /// Number of bytes of memory the computer has, or `None` if it is unlimited.
+    pub fn ram_size(&self) -> Option<usize> {
+        self.ram_size
+    }
+
+    /// Sets the number of bytes of memory the computer has. Takes effect the next time the computer
+    /// is launched. Guests whose initial memory does not fit fail to start.
+    pub fn set_ram_size(&mut self, ram_size: Option<usize>) {
+        self.ram_size = ram_size;
+    }
+
+    /// Seeds the computer's `/dev/random` and `/dev/urandom`, so that simulations are reproducible.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.devices.seed_random(seed);
+    }
+
+    /// Plugs in the radio, placing it at the computer's position, and returns its index.
+    pub fn add_wireless(&mut self, radio: AttachedRadio) -> usize {
+        radio.set_position(self.position);
+        self.devices.add_wireless(radio)
+    }
+
     pub fn root_dir(&self) -> PathBuf {
         let path = Path::new("out/computers");
         path.join(self.id.to_string())
@@ -58,48 +178,56 @@ impl Computer {
 
 pub struct ComputerVmState {
     wasi: WasiCtx,
-    stdout: Arc<RwLock<VecDeque<u8>>>,
-    stderr: Arc<RwLock<VecDeque<u8>>>,
-    stdin: Arc<RwLock<VecDeque<u8>>>,
+    stdout: Option<OutputStream>,
+    stderr: Option<OutputStream>,
     computer: Arc<RwLock<Computer>>,
+    limiter: RamLimiter,
 }
 
-// TODO: device number allocation table
 impl ComputerVmState {
     fn new(computer: Computer) -> Result<Self> {
-        let stdout = Arc::new(RwLock::new(VecDeque::new()));
-        let stderr = Arc::new(RwLock::new(VecDeque::new()));
-        let stdin = Arc::new(RwLock::new(VecDeque::new()));
+        let (stdout_writer, stdout) = output_stream();
+        let (stderr_writer, stderr) = output_stream();
 
         let wasi = WasiCtxBuilder::new()
-            .stdout(Box::new(WritePipe::from_shared(stdout.clone())))
-            .stderr(Box::new(WritePipe::from_shared(stderr.clone())))
-            .stdin(Box::new(ReadPipe::from_shared(stdin.clone())))
-            .preopened_dir(
-                // TODO: wrap tokio_wasi and shift inode up each by, say, 100
-                Dir::open_ambient_dir(computer.root_dir(), ambient_authority())?,
-                "/",
-            )?
-            .preopened_dir(
-                Dir::open_ambient_dir(computer.home_dir(), ambient_authority())?,
-                ".",
-            )?
+            .stdout(Box::new(WritePipe::new(stdout_writer)))
+            .stderr(Box::new(WritePipe::new(stderr_writer)))
+            .stdin(Box::new(StdinFile::new(
+                computer.devices.stdin(),
+                computer.devices.device_numbers().device_number("stdin", 0),
+            )))
             .env("RUST_BACKTRACE", "full")?
             .build();
 
+        let host_major = computer.devices.device_numbers().major("host").unwrap();
+        let host_devices = Arc::new(HostDevices::new(host_major, computer.id));
+        let root = Dir::open_ambient_dir(computer.root_dir(), ambient_authority())?;
+        let home = Dir::open_ambient_dir(computer.home_dir(), ambient_authority())?;
+        // `/dev` is mounted in the root, so its `..` is the root as the guest sees it
+        let root_inode = host_devices.remap_inode(root.dir_metadata()?.ino());
+
+        for (dir, path) in [(root, "/"), (home, ".")] {
+            let dir = HostDir::new(
+                Box::new(sync::dir::Dir::from_cap_std(dir)),
+                host_devices.clone(),
+            );
+            wasi.push_preopened_dir(Box::new(dir), path)?;
+        }
+
+        let limiter = RamLimiter::new(computer.ram_size());
         let computer = Arc::new(RwLock::new(computer));
 
         wasi.push_preopened_dir(
-            Box::new(DevicesDir::new(computer.clone())),
-            PathBuf::from("/dev/"),
+            Box::new(DevicesDir::new(computer.clone(), root_inode)),
+            PathBuf::from("/dev"),
         )?;
 
         Ok(ComputerVmState {
             wasi,
-            stdout,
-            stderr,
-            stdin,
+            stdout: Some(stdout),
+            stderr: Some(stderr),
             computer,
+            limiter,
         })
     }
 }
@@ -107,6 +235,9 @@ impl ComputerVmState {
 pub struct ComputerVm {
     main_thread: Func,
     store: Store<ComputerVmState>,
+    /// Set if the guest's CPU speed is limited, along with the fuel it is given per tick.
+    throttle: Option<(InHostCall, u64)>,
+    cpu_usage: Arc<CpuUsage>,
 }
 
 impl ComputerVm {
@@ -115,8 +246,30 @@ impl ComputerVm {
         computer: Computer,
         arg: &str,
     ) -> Result<ComputerVm> {
+        let cpu_speed = computer.cpu_speed();
         let mut store = Store::new(module.engine(), ComputerVmState::new(computer)?);
-        // store.epoch_deadline_async_yield_and_update(100); // TODO epoch interruption
+        store.limiter(|state| &mut state.limiter);
+
+        let throttle = match cpu_speed {
+            Some(fuel_per_tick) => {
+                // Running out of fuel preempts the guest instead, and the fuel yields are told
+                // apart from others by the guest not being in a host call, so epoch yields must
+                // never happen
+                store.set_epoch_deadline(u64::MAX / 2);
+                store.add_fuel(fuel_per_tick)?;
+                store.out_of_fuel_async_yield(u64::MAX, fuel_per_tick);
+
+                let in_host_call = InHostCall::default();
+                in_host_call.track(&mut store);
+                Some((in_host_call, fuel_per_tick))
+            }
+            None => {
+                store.set_epoch_deadline(EPOCH_TIME_SLICE);
+                store.epoch_deadline_async_yield_and_update(EPOCH_TIME_SLICE);
+                store.add_fuel(u64::MAX)?;
+                None
+            }
+        };
 
         // TODO: reuse linker
         let mut linker = Linker::new(module.engine());
@@ -131,10 +284,84 @@ impl ComputerVm {
         Ok(ComputerVm {
             main_thread: main_func,
             store,
+            throttle,
+            cpu_usage: Arc::default(),
         })
     }
 
-    pub fn add_ethernet(&mut self, link: AttachedDuplexLink) {
+    /// The guest's stdin, which input can be pushed into while the VM is running.
+    pub fn stdin(&self) -> Arc<Stdin> {
+        self.store.data().computer.read().unwrap().devices.stdin()
+    }
+
+    /// The computer's terminal, whose screen can be read while the VM is running.
+    pub fn tty(&self) -> Arc<Tty> {
+        self.store.data().computer.read().unwrap().devices.tty()
+    }
+
+    /// The computer's display, whose frames can be grabbed while the VM is running.
+    pub fn framebuffer(&self) -> Arc<Framebuffer> {
+        self.store
+            .data()
+            .computer
+            .read()
+            .unwrap()
+            .devices
+            .framebuffer()
+    }
+
+    /// The computer's keyboard, which key events can be injected into while the VM is running.
+    pub fn keyboard(&self) -> Arc<InputDevice> {
+        self.store
+            .data()
+            .computer
+            .read()
+            .unwrap()
+            .devices
+            .keyboard()
+    }
+
+    /// The computer's mouse, which pointer events can be injected into while the VM is running.
+    pub fn mouse(&self) -> Arc<InputDevice> {
+        self.store.data().computer.read().unwrap().devices.mouse()
+    }
+
+    /// Takes the stream of everything the guest writes to stdout, which can be read while the VM is
+    /// running. Returns `None` if it has already been taken.
+    pub fn take_stdout(&mut self) -> Option<OutputStream> {
+        self.store.data_mut().stdout.take()
+    }
+
+    /// Takes the stream of everything the guest writes to stderr, which can be read while the VM is
+    /// running. Returns `None` if it has already been taken.
+    pub fn take_stderr(&mut self) -> Option<OutputStream> {
+        self.store.data_mut().stderr.take()
+    }
+
+    /// The guest's memory usage, which can be watched for the guest running out of memory while
+    /// the VM is running.
+    pub fn memory_usage(&self) -> Arc<MemoryUsage> {
+        self.store.data().limiter.usage()
+    }
+
+    /// Number of wasm instructions the computer has run so far. See [`ComputerVm::cpu_usage`] to
+    /// watch it while the VM is running.
+    pub fn fuel_consumed(&self) -> u64 {
+        self.store.fuel_consumed().unwrap_or(0)
+    }
+
+    /// The guest's CPU usage, which can be read while the VM is running.
+    pub fn cpu_usage(&self) -> Arc<CpuUsage> {
+        self.cpu_usage.clone()
+    }
+
+    /// The computer this VM is running. Devices can be plugged in and unplugged through it while
+    /// the VM is running.
+    pub fn computer(&self) -> Arc<RwLock<Computer>> {
+        self.store.data().computer.clone()
+    }
+
+    pub fn add_ethernet(&mut self, link: AttachedDuplexLink) -> usize {
         self.store
             .data_mut()
             .computer
@@ -144,27 +371,77 @@ impl ComputerVm {
             .add_ethernet(link)
     }
 
+    pub fn remove_ethernet(&mut self, idx: usize) -> Option<AttachedDuplexLink> {
+        self.store
+            .data_mut()
+            .computer
+            .write()
+            .unwrap()
+            .devices_mut()
+            .remove_ethernet(idx)
+    }
+
+    pub fn add_device(&mut self, device: Arc<dyn Device>) -> usize {
+        self.store
+            .data_mut()
+            .computer
+            .write()
+            .unwrap()
+            .devices_mut()
+            .add_device(device)
+    }
+
+    pub fn remove_device(&mut self, idx: usize) -> Option<Arc<dyn Device>> {
+        self.store
+            .data_mut()
+            .computer
+            .write()
+            .unwrap()
+            .devices_mut()
+            .remove_device(idx)
+    }
+
+    pub fn add_wireless(&mut self, radio: AttachedRadio) -> usize {
+        self.store
+            .data_mut()
+            .computer
+            .write()
+            .unwrap()
+            .add_wireless(radio)
+    }
+
+    pub fn remove_wireless(&mut self, idx: usize) -> Option<AttachedRadio> {
+        self.store
+            .data_mut()
+            .computer
+            .write()
+            .unwrap()
+            .devices_mut()
+            .remove_wireless(idx)
+    }
+
+    pub fn set_position(&mut self, position: Position) {
+        self.store
+            .data_mut()
+            .computer
+            .write()
+            .unwrap()
+            .set_position(position)
+    }
+
     pub async fn resume(&mut self) -> Result<()> {
         let ty = self.main_thread.ty(&mut self.store);
         let mut results = vec![Val::null(); ty.results().len()];
-        let res = self
+        let call = self
             .main_thread
-            .call_async(&mut self.store, &[], &mut results)
-            .await;
-
-        let mut stdout = self.store.data().stdout.write().unwrap();
-        let stdout = BufReadDecoder::read_to_string_lossy(BufReader::new(&mut *stdout)).unwrap();
-
-        println!("=========================================");
-        println!(
-            "Computer {} finished execution",
-            self.store.data().computer.read().unwrap().id
-        );
-        println!("Stdout: {stdout}");
-
-        let mut stderr = self.store.data().stderr.write().unwrap();
-        let stderr = BufReadDecoder::read_to_string_lossy(BufReader::new(&mut *stderr)).unwrap();
-        println!("Stderr: {stderr}");
+            .call_async(&mut self.store, &[], &mut results);
+        let res = match self.throttle.clone() {
+            Some((in_host_call, fuel_per_tick)) => {
+                Throttled::new(call, in_host_call, fuel_per_tick, self.cpu_usage.clone()).await
+            }
+            None => call.await,
+        };
+        self.cpu_usage.set_fuel_consumed(self.fuel_consumed());
 
         match res {
             Ok(_) => Ok(()),
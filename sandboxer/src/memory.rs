@@ -0,0 +1,162 @@
+use event_listener::Event;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use wasmtime::ResourceLimiter;
+
+/// How much RAM a computer's guest is using, shared with the host while the guest runs.
+#[derive(Default)]
+pub struct MemoryUsage {
+    used: AtomicUsize,
+    out_of_memory: AtomicU64,
+    on_out_of_memory: Event,
+}
+
+impl MemoryUsage {
+    /// Number of bytes used by the guest's linear memories and tables.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+
+    /// Number of times the guest has been refused memory because it would exceed the RAM size.
+    pub fn out_of_memory_count(&self) -> u64 {
+        self.out_of_memory.load(Ordering::Acquire)
+    }
+
+    /// Waits until the guest is next refused memory.
+    pub async fn wait_for_out_of_memory(&self) {
+        let seen = self.out_of_memory_count();
+
+        loop {
+            let listener = self.on_out_of_memory.listen();
+            if self.out_of_memory_count() != seen {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
+
+/// Limits a guest's linear memories and tables to the computer's RAM size. Growth beyond it fails
+/// as `memory.grow` or `table.grow` would on a real machine, so the guest sees a normal allocation
+/// failure rather than a trap.
+pub(crate) struct RamLimiter {
+    ram_size: Option<usize>,
+    usage: Arc<MemoryUsage>,
+    /// Bytes added to `used` by the last growth permitted, taken back out if the growth fails.
+    last_growth: usize,
+}
+
+impl RamLimiter {
+    pub(crate) fn new(ram_size: Option<usize>) -> RamLimiter {
+        RamLimiter {
+            ram_size,
+            usage: Arc::default(),
+            last_growth: 0,
+        }
+    }
+
+    pub(crate) fn usage(&self) -> Arc<MemoryUsage> {
+        self.usage.clone()
+    }
+
+    fn grow(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool {
+        // Wasmtime refuses growth past the maximum itself, so it must not be counted, nor reported
+        // as running out of memory
+        if maximum.is_some_and(|maximum| desired > maximum) {
+            return false;
+        }
+
+        let growth = desired.saturating_sub(current);
+        let used = self.usage.used() + growth;
+
+        if self.ram_size.is_some_and(|ram_size| used > ram_size) {
+            self.usage.out_of_memory.fetch_add(1, Ordering::AcqRel);
+            self.usage.on_out_of_memory.notify(usize::MAX);
+            return false;
+        }
+
+        self.usage.used.store(used, Ordering::Release);
+        self.last_growth = growth;
+        true
+    }
+
+    fn grow_failed(&mut self) {
+        let growth = std::mem::take(&mut self.last_growth);
+        self.usage.used.fetch_sub(growth, Ordering::AcqRel);
+    }
+}
+
+/// Wasmtime uses a pointer for each table element.
+const TABLE_ELEMENT_SIZE: usize = std::mem::size_of::<usize>();
+
+impl ResourceLimiter for RamLimiter {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool {
+        self.grow(current, desired, maximum)
+    }
+
+    fn memory_grow_failed(&mut self, _error: &anyhow::Error) {
+        self.grow_failed();
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+        self.grow(
+            current as usize * TABLE_ELEMENT_SIZE,
+            desired as usize * TABLE_ELEMENT_SIZE,
+            maximum.map(|maximum| maximum as usize * TABLE_ELEMENT_SIZE),
+        )
+    }
+
+    fn table_grow_failed(&mut self, _error: &anyhow::Error) {
+        self.grow_failed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmtime::{Engine, Memory, MemoryType, Store};
+
+    const PAGE_SIZE: usize = 64 * 1024;
+
+    fn store(ram_size: Option<usize>) -> Store<RamLimiter> {
+        let mut store = Store::new(&Engine::default(), RamLimiter::new(ram_size));
+        store.limiter(|limiter| limiter);
+        store
+    }
+
+    #[test]
+    fn growth_past_maximum_is_not_counted() {
+        let mut store = store(None);
+        let usage = store.data().usage();
+        let memory = Memory::new(&mut store, MemoryType::new(1, Some(2))).unwrap();
+        assert_eq!(usage.used(), PAGE_SIZE);
+
+        assert!(memory.grow(&mut store, 5).is_err());
+        assert_eq!(usage.used(), PAGE_SIZE);
+        assert_eq!(usage.out_of_memory_count(), 0);
+
+        memory.grow(&mut store, 1).unwrap();
+        assert_eq!(usage.used(), 2 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn growth_past_ram_size_is_refused() {
+        let mut store = store(Some(2 * PAGE_SIZE));
+        let usage = store.data().usage();
+        let memory = Memory::new(&mut store, MemoryType::new(1, None)).unwrap();
+
+        assert!(memory.grow(&mut store, 2).is_err());
+        assert_eq!(usage.used(), PAGE_SIZE);
+        assert_eq!(usage.out_of_memory_count(), 1);
+    }
+
+    #[test]
+    fn failed_growth_is_taken_back() {
+        let mut limiter = RamLimiter::new(None);
+        assert!(limiter.memory_growing(0, PAGE_SIZE, None));
+        assert!(limiter.memory_growing(PAGE_SIZE, 3 * PAGE_SIZE, None));
+
+        limiter.memory_grow_failed(&anyhow::anyhow!("failed to allocate"));
+        assert_eq!(limiter.usage().used(), PAGE_SIZE);
+    }
+}
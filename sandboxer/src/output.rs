@@ -0,0 +1,128 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Most bytes of output kept for a stream which has not been read yet, or has not been taken.
+pub const MAX_BUFFERED_OUTPUT: usize = 1 << 20;
+
+/// Creates a stream for a guest to write to and the host to read from.
+pub(crate) fn output_stream() -> (OutputWriter, OutputStream) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let buffered = Arc::new(AtomicUsize::new(0));
+    (
+        OutputWriter {
+            tx,
+            buffered: buffered.clone(),
+        },
+        OutputStream {
+            rx,
+            buffered,
+            partial: Vec::new(),
+        },
+    )
+}
+
+/// Sends everything written to it over to an [`OutputStream`]. Output is discarded once the stream
+/// has been dropped, or while it has [`MAX_BUFFERED_OUTPUT`] bytes waiting to be read.
+pub(crate) struct OutputWriter {
+    tx: UnboundedSender<Vec<u8>>,
+    /// Bytes sent which the stream has not received yet.
+    buffered: Arc<AtomicUsize>,
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let space = MAX_BUFFERED_OUTPUT.saturating_sub(self.buffered.load(Ordering::Acquire));
+        let len = buf.len().min(space);
+
+        // Counted before sending, so that the stream never receives bytes which are not counted
+        if len > 0 {
+            self.buffered.fetch_add(len, Ordering::AcqRel);
+            let _ = self.tx.send(buf[..len].to_vec());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Output written by a guest to its stdout or stderr, received as it is produced. The stream ends
+/// once the VM has been dropped. Output written while [`MAX_BUFFERED_OUTPUT`] bytes are waiting to
+/// be read is lost.
+pub struct OutputStream {
+    rx: UnboundedReceiver<Vec<u8>>,
+    buffered: Arc<AtomicUsize>,
+    partial: Vec<u8>,
+}
+
+impl OutputStream {
+    async fn recv(&mut self) -> Option<Vec<u8>> {
+        let bytes = self.rx.recv().await?;
+        self.buffered.fetch_sub(bytes.len(), Ordering::AcqRel);
+        Some(bytes)
+    }
+
+    /// Receives the next bytes written by the guest, or `None` once the stream has ended.
+    pub async fn read(&mut self) -> Option<Vec<u8>> {
+        if !self.partial.is_empty() {
+            return Some(std::mem::take(&mut self.partial));
+        }
+        self.recv().await
+    }
+
+    /// Receives the next line written by the guest, without its line ending, or `None` once the
+    /// stream has ended. Invalid UTF-8 is replaced, and a final line without a line ending is
+    /// returned when the stream ends. Lines longer than [`MAX_BUFFERED_OUTPUT`] are split, so that
+    /// output without line endings is not kept forever.
+    pub async fn read_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+                let mut line: Vec<u8> = self.partial.drain(..=end).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            }
+
+            if self.partial.len() >= MAX_BUFFERED_OUTPUT {
+                let line: Vec<u8> = self.partial.drain(..MAX_BUFFERED_OUTPUT).collect();
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            }
+
+            match self.recv().await {
+                Some(bytes) => self.partial.extend_from_slice(&bytes),
+                None if self.partial.is_empty() => return None,
+                None => {
+                    let line = std::mem::take(&mut self.partial);
+                    return Some(String::from_utf8_lossy(&line).into_owned());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn splits_long_lines() {
+        let (mut writer, mut stream) = output_stream();
+
+        writer
+            .write_all(&vec![b'a'; MAX_BUFFERED_OUTPUT + 10])
+            .unwrap();
+        assert_eq!(
+            stream.read_line().await.unwrap(),
+            "a".repeat(MAX_BUFFERED_OUTPUT)
+        );
+
+        // Output beyond the cap was discarded, so the next line starts afresh
+        writer.write_all(b"bc\n").unwrap();
+        assert_eq!(stream.read_line().await.unwrap(), "bc");
+    }
+}
@@ -0,0 +1,114 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::time::{Interval, MissedTickBehavior};
+use wasmtime::Store;
+
+/// Runs a guest whose fuel is refilled by wasmtime every time it runs out, holding it back until
+/// the next CPU tick whenever it does. Wasmtime yields after running out of fuel while the guest is
+/// running its own code, whereas guests waiting on host calls are pending inside the call, so only
+/// the former are held back.
+pub(crate) struct Throttled<F> {
+    guest: Pin<Box<F>>,
+    in_host_call: InHostCall,
+    /// Fuel wasmtime refills the guest with each time it runs out.
+    fuel_per_tick: u64,
+    usage: Arc<CpuUsage>,
+    ticks: Interval,
+    exhausted: bool,
+}
+
+impl<F: Future> Throttled<F> {
+    pub(crate) fn new(
+        guest: F,
+        in_host_call: InHostCall,
+        fuel_per_tick: u64,
+        usage: Arc<CpuUsage>,
+    ) -> Throttled<F> {
+        let mut ticks = tokio::time::interval(crate::CPU_TICK);
+        // Ticks missed while the host was busy are lost, rather than letting the guest catch up
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // The first tick completes immediately
+        ticks.reset();
+
+        Throttled {
+            guest: Box::pin(guest),
+            in_host_call,
+            fuel_per_tick,
+            usage,
+            ticks,
+            exhausted: false,
+        }
+    }
+}
+
+impl<F: Future> Future for Throttled<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.exhausted {
+            if self.ticks.poll_tick(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.exhausted = false;
+        }
+
+        match self.guest.as_mut().poll(cx) {
+            Poll::Ready(output) => Poll::Ready(output),
+            Poll::Pending => {
+                // The guest has already woken itself, so the next poll waits for the tick
+                if !self.in_host_call.get() {
+                    self.exhausted = true;
+                    self.usage.add_fuel_consumed(self.fuel_per_tick);
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// How many instructions a computer's guest has run, shared with the host while the guest runs.
+#[derive(Default)]
+pub struct CpuUsage {
+    fuel_consumed: AtomicU64,
+}
+
+impl CpuUsage {
+    /// Number of wasm instructions the guest has run. While a guest with a CPU speed runs, this is
+    /// brought up to date every time its fuel is refilled, so it trails by less than a tick's worth
+    /// of instructions. Guests without a CPU speed are never refilled, so theirs is only brought up
+    /// to date whenever [`ComputerVm::resume`](crate::ComputerVm::resume) returns, as it also is
+    /// for those with one.
+    pub fn fuel_consumed(&self) -> u64 {
+        self.fuel_consumed.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_fuel_consumed(&self, fuel: u64) {
+        self.fuel_consumed.store(fuel, Ordering::Release);
+    }
+
+    fn add_fuel_consumed(&self, fuel: u64) {
+        self.fuel_consumed.fetch_add(fuel, Ordering::AcqRel);
+    }
+}
+
+/// Whether a guest is in the middle of a host call, kept up to date by a call hook on its store.
+#[derive(Clone, Default)]
+pub(crate) struct InHostCall(Arc<AtomicBool>);
+
+impl InHostCall {
+    /// Installs the call hook which keeps this up to date on the guest's store.
+    pub(crate) fn track<T>(&self, store: &mut Store<T>) {
+        let in_host_call = self.0.clone();
+        store.call_hook(move |_data, hook| {
+            in_host_call.store(hook.entering_host(), Ordering::Release);
+            Ok(())
+        });
+    }
+
+    fn get(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}